@@ -18,4 +18,7 @@
 
 # If you keep the line number information, uncomment this to
 # hide the original source file name.
-#-renamesourcefileattribute SourceFile
\ No newline at end of file
+#-renamesourcefileattribute SourceFile
+# Fields of the parameter classes are read by name and the callbacks are invoked from the native
+# RAW processor
+-keep class com.mdnssknght.mycamera.processing.** { *; }
@@ -5,6 +5,7 @@ import android.view.View
 import androidx.appcompat.app.AppCompatActivity
 import com.mdnssknght.mycamera.databinding.ActivityCameraBinding
 import com.mdnssknght.mycamera.processing.RawProcessor
+import java.io.File
 
 class CameraActivity : AppCompatActivity() {
 
@@ -16,7 +17,7 @@ class CameraActivity : AppCompatActivity() {
         activityCameraBinding = ActivityCameraBinding.inflate(layoutInflater)
         setContentView(activityCameraBinding.root)
 
-        RawProcessor.init()
+        RawProcessor.init(File(cacheDir, PIPELINE_CACHE_FILE).absolutePath)
     }
 
     override fun onDestroy() {
@@ -25,6 +26,12 @@ class CameraActivity : AppCompatActivity() {
         RawProcessor.fini()
     }
 
+    override fun onTrimMemory(level: Int) {
+        super.onTrimMemory(level)
+
+        RawProcessor.trim()
+    }
+
     override fun onResume() {
         super.onResume()
 
@@ -47,5 +54,8 @@ class CameraActivity : AppCompatActivity() {
         const val ANIMATION_FAST_MILLIS = 50L
         const val ANIMATION_SLOW_MILLIS = 100L
         private const val IMMERSIVE_FLAGS_TIMEOUT = 500L
+
+        // Vulkan pipeline cache kept between launches to skip shader compilation.
+        private const val PIPELINE_CACHE_FILE = "pipeline_cache.bin"
     }
 }
\ No newline at end of file
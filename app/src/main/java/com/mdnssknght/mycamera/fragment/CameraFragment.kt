@@ -9,6 +9,7 @@ import android.hardware.camera2.CameraCaptureSession
 import android.hardware.camera2.CameraCharacteristics
 import android.hardware.camera2.CameraDevice
 import android.hardware.camera2.CameraManager
+import android.hardware.camera2.CameraMetadata
 import android.hardware.camera2.CaptureRequest
 import android.hardware.camera2.CaptureResult
 import android.hardware.camera2.DngCreator
@@ -38,7 +39,17 @@ import androidx.navigation.fragment.navArgs
 import com.mdnssknght.mycamera.R
 import com.mdnssknght.mycamera.activity.CameraActivity
 import com.mdnssknght.mycamera.databinding.FragmentCameraBinding
+import com.mdnssknght.mycamera.processing.ColorSpace
+import com.mdnssknght.mycamera.processing.DemosaicAlgorithm
+import com.mdnssknght.mycamera.processing.HighlightMode
+import com.mdnssknght.mycamera.processing.OutputFormat
+import com.mdnssknght.mycamera.processing.ProcessingParams
+import com.mdnssknght.mycamera.processing.RawFrame
 import com.mdnssknght.mycamera.processing.RawProcessor
+import com.mdnssknght.mycamera.processing.RawProcessorCallback
+import com.mdnssknght.mycamera.processing.RawProcessorException
+import com.mdnssknght.mycamera.processing.ToneMapOperator
+import com.mdnssknght.mycamera.processing.WhiteBalance
 import com.mdnssknght.mycamera.util.OrientationLiveData
 import com.mdnssknght.mycamera.util.computeExifOrientation
 import com.mdnssknght.mycamera.util.getPreviewOutputSize4x3
@@ -246,117 +257,149 @@ class CameraFragment : Fragment() {
 
                         // If the result is a RAW file, then pass its data for further processing.
                         "dng" -> {
-                            val outputBuffer: ByteBuffer
-                            val width: Int
-                            val height: Int
-
-                            result.image.let { it ->
-                                width = it.planes[0].rowStride / it.planes[0].pixelStride
-                                height = it.height
+                            // Hacky, I know
+                            try {
+                                result.image.let { it ->
+                                    val width = it.width
+                                    val height = it.height
+
+                                    val colorFilterArrangement = characteristics.get(
+                                        CameraCharacteristics.SENSOR_INFO_COLOR_FILTER_ARRANGEMENT
+                                    )!!
+
+                                    val neutralPoint = FloatArray(3).also {
+                                        result.metadata.get(CaptureResult.SENSOR_NEUTRAL_COLOR_POINT)!!
+                                            .forEachIndexed { index, rational ->
+                                                it[index] = rational.toFloat()
+                                            }
+                                    }
 
-                                val outputBytes = ByteArray(width * height * 4)
+                                    val colorGains = FloatArray(4).also {
+                                        result.metadata.get(CaptureResult.COLOR_CORRECTION_GAINS)!!
+                                            .copyTo(it, 0)
+                                    }
 
-                                val colorFilterArrangement = characteristics.get(
-                                    CameraCharacteristics.SENSOR_INFO_COLOR_FILTER_ARRANGEMENT
-                                )!!
+                                    val whiteLevel =
+                                        characteristics.get(CameraCharacteristics.SENSOR_INFO_WHITE_LEVEL)!!
 
-                                val neutralPoint = FloatArray(3).also {
-                                    result.metadata.get(CaptureResult.SENSOR_NEUTRAL_COLOR_POINT)!!
-                                        .forEachIndexed { index, rational ->
-                                            it[index] = rational.toFloat()
-                                        }
-                                }
-
-                                val colorGains = FloatArray(4).also {
-                                    result.metadata.get(CaptureResult.COLOR_CORRECTION_GAINS)!!
-                                        .copyTo(it, 0)
-                                }
+                                    val blackLevel = IntArray(4).also {
+                                        characteristics.get(CameraCharacteristics.SENSOR_BLACK_LEVEL_PATTERN)!!
+                                            .copyTo(it, 0)
+                                    }
 
-                                val whiteLevel =
-                                    characteristics.get(CameraCharacteristics.SENSOR_INFO_WHITE_LEVEL)!!
+                                    val rationalDestination = arrayOfNulls<Rational>(9)
 
-                                val blackLevel = IntArray(4).also {
-                                    characteristics.get(CameraCharacteristics.SENSOR_BLACK_LEVEL_PATTERN)!!
-                                        .copyTo(it, 0)
-                                }
+                                    val forwardMatrix1 = FloatArray(9).also {
+                                        characteristics.get(CameraCharacteristics.SENSOR_FORWARD_MATRIX1)!!
+                                            .copyElements(rationalDestination, 0)
+                                        rationalDestination.forEachIndexed { index, rational ->
+                                            it[index] = rational!!.toFloat()
+                                        }
 
-                                val rationalDestination = arrayOfNulls<Rational>(9)
+                                    }
 
-                                val forwardMatrix1 = FloatArray(9).also {
-                                    characteristics.get(CameraCharacteristics.SENSOR_FORWARD_MATRIX1)!!
-                                        .copyElements(rationalDestination, 0)
-                                    rationalDestination.forEachIndexed { index, rational ->
-                                        it[index] = rational!!.toFloat()
+                                    val forwardMatrix2 = FloatArray(9).also {
+                                        characteristics.get(CameraCharacteristics.SENSOR_FORWARD_MATRIX2)
+                                            ?.copyElements(rationalDestination, 0)
+                                            ?: return@also
+                                        rationalDestination.forEachIndexed { index, rational ->
+                                            it[index] = rational!!.toFloat()
+                                        }
                                     }
 
-                                }
+                                    val referenceIlluminant1 =
+                                        characteristics.get(CameraCharacteristics.SENSOR_REFERENCE_ILLUMINANT1)!!
 
-                                val forwardMatrix2 = FloatArray(9).also {
-                                    characteristics.get(CameraCharacteristics.SENSOR_FORWARD_MATRIX2)!!
-                                        .copyElements(rationalDestination, 0)
-                                    rationalDestination.forEachIndexed { index, rational ->
-                                        it[index] = rational!!.toFloat()
-                                    }
-                                }
+                                    // Devices calibrated for a single illuminant leave the second one unset
+                                    val referenceIlluminant2 =
+                                        characteristics.get(CameraCharacteristics.SENSOR_REFERENCE_ILLUMINANT2)
+                                            ?.toInt() ?: 0
 
-                                val colorCorrectionTransform = FloatArray(9).also {
-                                    result.metadata.get(CaptureResult.COLOR_CORRECTION_TRANSFORM)!!
-                                        .copyElements(rationalDestination, 0)
-                                    rationalDestination.forEachIndexed { index, rational ->
-                                        it[index] = rational!!.toFloat()
+                                    val colorTransform1 = FloatArray(9).also {
+                                        characteristics.get(CameraCharacteristics.SENSOR_COLOR_TRANSFORM1)!!
+                                            .copyElements(rationalDestination, 0)
+                                        rationalDestination.forEachIndexed { index, rational ->
+                                            it[index] = rational!!.toFloat()
+                                        }
                                     }
-                                }
 
-                                RawProcessor.process(
-                                    width,
-                                    height,
-                                    it.planes[0].buffer,
-                                    outputBytes,
-                                    colorFilterArrangement,
-                                    whiteLevel,
-                                    blackLevel,
-                                    neutralPoint,
-                                    colorGains,
-                                    colorCorrectionTransform,
-                                    forwardMatrix1,
-                                    forwardMatrix2
-                                )
+                                    val colorTransform2 = FloatArray(9).also {
+                                        characteristics.get(CameraCharacteristics.SENSOR_COLOR_TRANSFORM2)
+                                            ?.copyElements(rationalDestination, 0)
+                                            ?: return@also
+                                        rationalDestination.forEachIndexed { index, rational ->
+                                            it[index] = rational!!.toFloat()
+                                        }
+                                    }
 
-                                outputBuffer = ByteBuffer.wrap(outputBytes)
-                            }
+                                    val frame = RawFrame(
+                                        data = it.planes[0].buffer,
+                                        format = it.format,
+                                        width = width,
+                                        height = height,
+                                        rowStride = it.planes[0].rowStride,
+                                        pixelStride = it.planes[0].pixelStride,
+                                        colorFilterArrangement = colorFilterArrangement,
+                                        whiteLevel = whiteLevel,
+                                        blackLevel = blackLevel,
+                                    )
 
-                            // Hacky, I know
-                            try {
-                                val bitmap = createBitmap(width, height)
-                                    .apply { copyPixelsFromBuffer(outputBuffer) }
-
-                                val file = createFile("jpg")
-                                FileOutputStream(file).use { it ->
-                                    bitmap.compress(
-                                        Bitmap.CompressFormat.JPEG,
-                                        100, it
+                                    val params = ProcessingParams(
+                                        neutralPoint = neutralPoint,
+                                        colorGains = colorGains,
+                                        referenceIlluminant1 = referenceIlluminant1,
+                                        referenceIlluminant2 = referenceIlluminant2,
+                                        colorTransform1 = colorTransform1,
+                                        colorTransform2 = colorTransform2,
+                                        forwardMatrix1 = forwardMatrix1,
+                                        forwardMatrix2 = forwardMatrix2,
+                                        whiteBalance = WhiteBalance.Camera,
+                                        demosaicAlgorithm = DemosaicAlgorithm.MENON,
+                                        highlightMode = HighlightMode.INPAINT,
+                                        lensShadingMap = result.metadata.get(
+                                            CaptureResult.STATISTICS_LENS_SHADING_CORRECTION_MAP
+                                        ),
+                                        defectThreshold = DEFECT_THRESHOLD,
+                                        badPixels = result.metadata.get(
+                                            CaptureResult.STATISTICS_HOT_PIXEL_MAP
+                                        ),
+                                        noiseProfile = result.metadata.get(
+                                            CaptureResult.SENSOR_NOISE_PROFILE
+                                        ),
+                                        denoiseStrength = DENOISE_STRENGTH,
+                                        localToneMapStrength = LOCAL_TONE_MAP_STRENGTH,
+                                        syntheticExposures = SYNTHETIC_EXPOSURES,
+                                        toneMapOperator = ToneMapOperator.Aces,
+                                        exposureBias = EXPOSURE_BIAS,
+                                        colorSpace = ColorSpace.SRGB,
+                                        outputFormat = OutputFormat.RGBA8,
                                     )
-                                }
 
-                                ExifInterface(file.absolutePath).let { exif ->
-                                    exif.setAttribute(
-                                        ExifInterface.TAG_ORIENTATION,
-                                        result.orientation.toString()
+                                    // The image is closed as soon as this block returns, the
+                                    // frame has already been uploaded by then
+                                    RawProcessor.submit(
+                                        frame,
+                                        params,
+                                        object : RawProcessorCallback {
+                                            override fun onProcessed(output: ByteArray) {
+                                                saveProcessedResult(
+                                                    output,
+                                                    width,
+                                                    height,
+                                                    result.orientation
+                                                )
+                                            }
+
+                                            override fun onError(exception: RawProcessorException) {
+                                                Log.e(TAG, "Error processing RAW image", exception)
+                                            }
+                                        }
                                     )
-                                    exif.saveAttributes()
-                                    Log.d(TAG, "EXIF metadata saved: ${output.absolutePath}")
                                 }
-
-                                // Even more hacky. Yes, I know
-                                MediaScannerConnection.scanFile(
-                                    context,
-                                    arrayOf(file.absolutePath),
-                                    null,
-                                    null
-                                )
-
+                            } catch (e: RawProcessorException) {
+                                Log.e(TAG, "Error processing RAW image", e)
                             } catch (e: Exception) {
-                                Log.e(TAG, "Error saving processed JPEG", e)
+                                Log.e(TAG, "Error submitting RAW image", e)
                             }
                         }
 
@@ -471,7 +514,16 @@ class CameraFragment : Fragment() {
 
         val captureRequest =
             session.device.createCaptureRequest(CameraDevice.TEMPLATE_STILL_CAPTURE)
-                .apply { addTarget(imageReader.surface) }
+                .apply {
+                    addTarget(imageReader.surface)
+                    // The shading map is applied by the RAW processor
+                    set(
+                        CaptureRequest.STATISTICS_LENS_SHADING_MAP_MODE,
+                        CameraMetadata.STATISTICS_LENS_SHADING_MAP_MODE_ON
+                    )
+                    // Static defects are replaced along with the detected ones
+                    set(CaptureRequest.STATISTICS_HOT_PIXEL_MAP_MODE, true)
+                }
 
         session.capture(captureRequest.build(), object : CameraCaptureSession.CaptureCallback() {
 
@@ -542,6 +594,41 @@ class CameraFragment : Fragment() {
         }, cameraHandler)
     }
 
+    /** Helper function used to save the output of the [RawProcessor] as a JPEG [File]. */
+    private fun saveProcessedResult(output: ByteArray, width: Int, height: Int, orientation: Int) {
+        try {
+            val bitmap = createBitmap(width, height)
+                .apply { copyPixelsFromBuffer(ByteBuffer.wrap(output)) }
+
+            val file = createFile("jpg")
+            FileOutputStream(file).use { it ->
+                bitmap.compress(
+                    Bitmap.CompressFormat.JPEG,
+                    100, it
+                )
+            }
+
+            ExifInterface(file.absolutePath).let { exif ->
+                exif.setAttribute(
+                    ExifInterface.TAG_ORIENTATION,
+                    orientation.toString()
+                )
+                exif.saveAttributes()
+                Log.d(TAG, "EXIF metadata saved: ${file.absolutePath}")
+            }
+
+            // Even more hacky. Yes, I know
+            MediaScannerConnection.scanFile(
+                context,
+                arrayOf(file.absolutePath),
+                null,
+                null
+            )
+        } catch (e: Exception) {
+            Log.e(TAG, "Error saving processed JPEG", e)
+        }
+    }
+
     /** Helper function used to save a [CombinedCaptureResult] into a [File]. */
     private suspend fun saveResult(result: CombinedCaptureResult): File = suspendCoroutine { cont ->
         when (result.format) {
@@ -616,6 +703,24 @@ class CameraFragment : Fragment() {
         /** Maximum time allowed to wait for the result of an image capture. */
         private const val IMAGE_CAPTURE_TIMEOUT_MILLIS: Long = 5000
 
+        /**
+         * Fraction of the white level by which a sample must leave the range of its same color
+         * neighbors to be replaced as a hot or dead pixel.
+         */
+        private const val DEFECT_THRESHOLD: Float = 0.1f
+
+        /** Range sigma of the raw denoising filter, in noise standard deviations. */
+        private const val DENOISE_STRENGTH: Float = 1.5f
+
+        /** Spread in EV of the synthetic exposures fused by the local tone mapping. */
+        private const val LOCAL_TONE_MAP_STRENGTH: Float = 4f
+
+        /** Number of synthetic exposures fused by the local tone mapping, from 2 to 4. */
+        private const val SYNTHETIC_EXPOSURES: Int = 3
+
+        /** Exposure compensation applied ahead of tone mapping, in EV. */
+        private const val EXPOSURE_BIAS: Float = 0f
+
         /** Helper data class used to hold capture metadata with their associated image. */
         data class CombinedCaptureResult(
             val image: Image,
@@ -0,0 +1,28 @@
+package com.mdnssknght.mycamera.processing
+
+/**
+ * Primaries and transfer function of the image produced by the native RAW processor. Passed by
+ * ordinal, keep the order in sync with the native side.
+ */
+enum class ColorSpace {
+    /** IEC 61966-2-1 sRGB. */
+    SRGB,
+
+    /** DCI-P3 primaries with the D65 white point and the sRGB transfer function. */
+    DISPLAY_P3,
+
+    /** ITU-R BT.2020 with the BT.709 transfer function. */
+    REC2020,
+
+    /** ITU-R BT.2100 perceptual quantizer, reference white at 203 cd/m², highlights up to 1000. */
+    REC2020_PQ,
+
+    /** ITU-R BT.2100 hybrid log-gamma. */
+    REC2020_HLG,
+
+    /** ROMM RGB, wide gamut relative to D50. */
+    PROPHOTO,
+
+    /** Scene referred linear BT.2020, neither tone mapped nor encoded. */
+    LINEAR,
+}
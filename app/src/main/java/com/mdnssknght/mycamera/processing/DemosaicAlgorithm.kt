@@ -0,0 +1,16 @@
+package com.mdnssknght.mycamera.processing
+
+/**
+ * Demosaicing algorithm used by the native RAW processor. Passed by ordinal, keep the order in
+ * sync with the native side.
+ */
+enum class DemosaicAlgorithm {
+    /** Fast and soft, meant for previews. */
+    BILINEAR,
+
+    /** Gradient corrected linear interpolation. */
+    MALVAR_HE_CUTLER,
+
+    /** Edge directed interpolation, fewer zipper and false color artifacts on fine detail. */
+    MENON,
+}
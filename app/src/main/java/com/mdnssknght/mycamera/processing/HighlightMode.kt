@@ -0,0 +1,16 @@
+package com.mdnssknght.mycamera.processing
+
+/**
+ * Handling of samples clipped by the sensor in the native RAW processor. Passed by ordinal, keep
+ * the order in sync with the native side.
+ */
+enum class HighlightMode {
+    /** Clips every channel at the lowest clipping point, clipped highlights turn white. */
+    CLIP,
+
+    /** Desaturates towards white approaching the clipping point. */
+    BLEND,
+
+    /** Estimates clipped channels from the unclipped ones and the color of nearby pixels. */
+    INPAINT,
+}
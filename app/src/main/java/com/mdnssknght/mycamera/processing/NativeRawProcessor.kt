@@ -1,7 +1,5 @@
 package com.mdnssknght.mycamera.processing
 
-import java.nio.ByteBuffer
-
 class NativeRawProcessor {
 
     companion object {
@@ -9,24 +7,58 @@ class NativeRawProcessor {
             System.loadLibrary("raw_processor")
         }
 
-        external fun nativeInit(): Long
+        @Throws(RawProcessorException::class)
+        external fun nativeInit(pipelineCachePath: String?): Long
 
         external fun nativeFini(handle: Long)
 
+        external fun nativeTrim(handle: Long)
+
+        @Throws(RawProcessorException::class)
         external fun nativeProcess(
             handle: Long,
-            width: Int,
-            height: Int,
-            data: ByteBuffer,
+            frame: RawFrame,
+            params: ProcessingParams,
             out: ByteArray,
-            colorFilterArrangement: Int,
-            whiteLevel: Int,
-            blackLevel: IntArray,
-            neutralPoint: FloatArray,
-            colorGains: FloatArray,
-            colorCorrectionTransform: FloatArray,
+        )
+
+        @Throws(RawProcessorException::class)
+        external fun nativeSubmit(
+            handle: Long,
+            frame: RawFrame,
+            params: ProcessingParams,
+            callback: RawProcessorCallback,
+        )
+
+        @Throws(RawProcessorException::class)
+        external fun nativeSubmitBurst(
+            handle: Long,
+            frames: Array<RawFrame>,
+            params: ProcessingParams,
+            callback: RawProcessorCallback,
+        )
+
+        @Throws(RawProcessorException::class)
+        external fun nativeTemperatureToGains(
+            temperature: Float,
+            tint: Float,
+            referenceIlluminant1: Int,
+            referenceIlluminant2: Int,
+            colorTransform1: FloatArray,
+            colorTransform2: FloatArray,
             forwardMatrix1: FloatArray,
             forwardMatrix2: FloatArray,
-        )
+        ): FloatArray
+
+        @Throws(RawProcessorException::class)
+        external fun nativeGainsToTemperature(
+            gains: FloatArray,
+            referenceIlluminant1: Int,
+            referenceIlluminant2: Int,
+            colorTransform1: FloatArray,
+            colorTransform2: FloatArray,
+            forwardMatrix1: FloatArray,
+            forwardMatrix2: FloatArray,
+        ): FloatArray
     }
 }
\ No newline at end of file
@@ -0,0 +1,20 @@
+package com.mdnssknght.mycamera.processing
+
+/**
+ * Pixel layout of the image produced by the native RAW processor, rows are tightly packed and
+ * components are in native byte order. Passed by ordinal, keep the order in sync with the native
+ * side.
+ */
+enum class OutputFormat(val bytesPerPixel: Int) {
+    /** 8-bit unsigned normalized RGBA, matches [android.graphics.Bitmap.Config.ARGB_8888]. */
+    RGBA8(4),
+
+    /** 16-bit unsigned normalized RGB without alpha. */
+    RGB16(6),
+
+    /** Half precision floating-point RGBA, matches [android.graphics.Bitmap.Config.RGBA_F16]. */
+    RGBA16F(8),
+
+    /** Single precision floating-point RGBA. */
+    RGBA32F(16),
+}
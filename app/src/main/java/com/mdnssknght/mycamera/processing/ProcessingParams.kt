@@ -0,0 +1,81 @@
+package com.mdnssknght.mycamera.processing
+
+import android.graphics.Point
+import android.hardware.camera2.params.LensShadingMap
+import android.util.Pair
+
+/**
+ * Capture metadata and processing options of the native RAW processor, named after the Camera2
+ * keys they come from. The fields are read by name by the native side, keep them in sync.
+ *
+ * @property neutralPoint CaptureResult.SENSOR_NEUTRAL_COLOR_POINT.
+ * @property colorGains CaptureResult.COLOR_CORRECTION_GAINS.
+ * @property referenceIlluminant2 Zero when the device is calibrated for a single illuminant.
+ * @property colorTransform1 CameraCharacteristics.SENSOR_COLOR_TRANSFORM1, row major.
+ * @property forwardMatrix1 CameraCharacteristics.SENSOR_FORWARD_MATRIX1, row major.
+ * @property whiteBalance Replaces the neutral point and the color gains unless it is
+ * [WhiteBalance.Camera].
+ * @property defectThreshold Samples further than this fraction of the white level outside the
+ * range of their same color neighbors are replaced, zero disables the detection.
+ * @property denoiseStrength Range sigma of the denoising filter in units of noise standard
+ * deviation, zero disables the denoising.
+ * @property localToneMapStrength Spread in EV of the synthetic exposures fused by the local tone
+ * mapping, zero disables it.
+ * @property syntheticExposures Number of synthetic exposures, from 2 to 4.
+ * @property exposureBias Exposure compensation in EV applied ahead of the tone mapping operator.
+ */
+class ProcessingParams(
+    val neutralPoint: FloatArray,
+    val colorGains: FloatArray,
+    val referenceIlluminant1: Int,
+    val referenceIlluminant2: Int,
+    val colorTransform1: FloatArray,
+    val colorTransform2: FloatArray,
+    val forwardMatrix1: FloatArray,
+    val forwardMatrix2: FloatArray,
+    val whiteBalance: WhiteBalance = WhiteBalance.Camera,
+    val demosaicAlgorithm: DemosaicAlgorithm = DemosaicAlgorithm.MALVAR_HE_CUTLER,
+    val highlightMode: HighlightMode = HighlightMode.INPAINT,
+    lensShadingMap: LensShadingMap? = null,
+    val defectThreshold: Float = 0f,
+    badPixels: Array<Point>? = null,
+    noiseProfile: Array<Pair<Double, Double>>? = null,
+    val denoiseStrength: Float = 0f,
+    val localToneMapStrength: Float = 0f,
+    val syntheticExposures: Int = 3,
+    val toneMapOperator: ToneMapOperator = ToneMapOperator.Aces,
+    val exposureBias: Float = 0f,
+    val colorSpace: ColorSpace = ColorSpace.SRGB,
+    val outputFormat: OutputFormat = OutputFormat.RGBA8,
+) {
+    // CaptureResult.STATISTICS_LENS_SHADING_CORRECTION_MAP, [R, Geven, Godd, B] gain factors
+    // of each control point in row major order
+    private val lensShadingGains: FloatArray? =
+        lensShadingMap?.let { map ->
+            FloatArray(map.gainFactorCount).also { map.copyGainFactors(it, 0) }
+        }
+    private val lensShadingColumns: Int = lensShadingMap?.columnCount ?: 0
+    private val lensShadingRows: Int = lensShadingMap?.rowCount ?: 0
+
+    // CaptureResult.STATISTICS_HOT_PIXEL_MAP as x, y pairs
+    private val badPixelCoordinates: IntArray? =
+        badPixels?.let { points ->
+            IntArray(points.size * 2).also { coordinates ->
+                points.forEachIndexed { index, point ->
+                    coordinates[index * 2] = point.x
+                    coordinates[index * 2 + 1] = point.y
+                }
+            }
+        }
+
+    // CaptureResult.SENSOR_NOISE_PROFILE as (S, O) pairs of each channel of the sensor mosaic
+    private val noiseProfileCoefficients: FloatArray? =
+        noiseProfile?.let { pairs ->
+            FloatArray(pairs.size * 2).also { coefficients ->
+                pairs.forEachIndexed { index, pair ->
+                    coefficients[index * 2] = pair.first.toFloat()
+                    coefficients[index * 2 + 1] = pair.second.toFloat()
+                }
+            }
+        }
+}
@@ -0,0 +1,28 @@
+package com.mdnssknght.mycamera.processing
+
+import java.nio.ByteBuffer
+
+/**
+ * Bayer RAW frame as delivered by the camera. The fields are read by name by the native RAW
+ * processor, keep them in sync with the native side.
+ *
+ * @property data Direct buffer of the first plane, such as Image.Plane.getBuffer.
+ * @property format android.graphics.ImageFormat of the image, RAW_SENSOR, RAW10 or RAW12.
+ * @property rowStride Image.Plane.getRowStride, rows may be padded past the end of the samples.
+ * @property pixelStride Image.Plane.getPixelStride, 2 for RAW_SENSOR and 0 for the packed formats.
+ * @property colorFilterArrangement CameraCharacteristics.SENSOR_INFO_COLOR_FILTER_ARRANGEMENT.
+ * @property whiteLevel CameraCharacteristics.SENSOR_INFO_WHITE_LEVEL.
+ * @property blackLevel CameraCharacteristics.SENSOR_BLACK_LEVEL_PATTERN, one value per channel of
+ * the sensor mosaic in sensor order.
+ */
+class RawFrame(
+    val data: ByteBuffer,
+    val format: Int,
+    val width: Int,
+    val height: Int,
+    val rowStride: Int,
+    val pixelStride: Int,
+    val colorFilterArrangement: Int,
+    val whiteLevel: Int,
+    val blackLevel: IntArray,
+)
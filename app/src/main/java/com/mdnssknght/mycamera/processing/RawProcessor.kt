@@ -1,51 +1,93 @@
 package com.mdnssknght.mycamera.processing
 
-import java.nio.ByteBuffer
-
 object RawProcessor {
     private var pointerHandle: Long = 0
 
-    init {
-        pointerHandle = NativeRawProcessor.nativeInit()
-    }
-
-    fun init() {
+    fun init(pipelineCachePath: String?) {
         // Because this is an object we want the pointer to the handle to be initialized
         // only once.
+        if (pointerHandle == 0L) {
+            pointerHandle = NativeRawProcessor.nativeInit(pipelineCachePath)
+        }
     }
 
     fun fini() {
+        // Also writes the compiled pipelines back to the cache file
         NativeRawProcessor.nativeFini(pointerHandle)
+        pointerHandle = 0
+    }
+
+    fun trim() {
+        // Releases the GPU images and buffers kept around for the next capture
+        NativeRawProcessor.nativeTrim(pointerHandle)
+    }
+
+    fun process(frame: RawFrame, params: ProcessingParams, out: ByteArray) {
+        NativeRawProcessor.nativeProcess(pointerHandle, frame, params, out)
+    }
+
+    fun submit(frame: RawFrame, params: ProcessingParams, callback: RawProcessorCallback) {
+        // Returns once the frame is uploaded, the data buffer can be released right away
+        NativeRawProcessor.nativeSubmit(pointerHandle, frame, params, callback)
     }
 
-    fun process(
-        width: Int,
-        height: Int,
-        data: ByteBuffer,
-        out: ByteArray,
-        colorFilterArrangement: Int,
-        whiteLevel: Int,
-        blackLevel: IntArray,
-        neutralPoint: FloatArray,
-        colorGains: FloatArray,
-        colorCorrectionTransform: FloatArray,
+    fun submitBurst(
+        frames: Array<RawFrame>,
+        params: ProcessingParams,
+        callback: RawProcessorCallback,
+    ) {
+        // Frames are aligned to and merged into the first one, whose metadata is used for the
+        // whole burst. Returns once every frame is uploaded.
+        NativeRawProcessor.nativeSubmitBurst(pointerHandle, frames, params, callback)
+    }
+
+    /**
+     * Gains of the [R, Geven, Godd, B] channels that neutralize a scene illuminant of the given
+     * temperature in Kelvin and tint, for the calibration reported by the camera.
+     */
+    fun temperatureToGains(
+        temperature: Float,
+        tint: Float,
+        referenceIlluminant1: Int,
+        referenceIlluminant2: Int,
+        colorTransform1: FloatArray,
+        colorTransform2: FloatArray,
         forwardMatrix1: FloatArray,
         forwardMatrix2: FloatArray,
-    ) {
-        NativeRawProcessor.nativeProcess(
-            pointerHandle,
-            width,
-            height,
-            data,
-            out,
-            colorFilterArrangement,
-            whiteLevel,
-            blackLevel,
-            neutralPoint,
-            colorGains,
-            colorCorrectionTransform,
+    ): FloatArray = NativeRawProcessor.nativeTemperatureToGains(
+        temperature,
+        tint,
+        referenceIlluminant1,
+        referenceIlluminant2,
+        colorTransform1,
+        colorTransform2,
+        forwardMatrix1,
+        forwardMatrix2
+    )
+
+    /**
+     * Temperature and tint of the scene illuminant neutralized by the gains, such as
+     * COLOR_CORRECTION_GAINS, to display the white balance of a capture.
+     */
+    fun gainsToTemperature(
+        gains: FloatArray,
+        referenceIlluminant1: Int,
+        referenceIlluminant2: Int,
+        colorTransform1: FloatArray,
+        colorTransform2: FloatArray,
+        forwardMatrix1: FloatArray,
+        forwardMatrix2: FloatArray,
+    ): WhiteBalance.Temperature {
+        val (temperature, tint) = NativeRawProcessor.nativeGainsToTemperature(
+            gains,
+            referenceIlluminant1,
+            referenceIlluminant2,
+            colorTransform1,
+            colorTransform2,
             forwardMatrix1,
             forwardMatrix2
         )
+
+        return WhiteBalance.Temperature(temperature, tint)
     }
 }
\ No newline at end of file
@@ -0,0 +1,11 @@
+package com.mdnssknght.mycamera.processing
+
+/**
+ * Receives the result of [RawProcessor.submit]. Invoked from a native worker thread.
+ */
+interface RawProcessorCallback {
+    /** Tightly packed pixels in the requested [OutputFormat], components in native byte order. */
+    fun onProcessed(output: ByteArray)
+
+    fun onError(exception: RawProcessorException)
+}
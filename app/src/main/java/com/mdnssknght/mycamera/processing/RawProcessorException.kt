@@ -0,0 +1,6 @@
+package com.mdnssknght.mycamera.processing
+
+/**
+ * Thrown by the native RAW processor when initialization or processing fails.
+ */
+class RawProcessorException(message: String) : RuntimeException(message)
@@ -0,0 +1,19 @@
+package com.mdnssknght.mycamera.processing
+
+/**
+ * Tone mapping operator applied by the native RAW processor ahead of the transfer curve. The
+ * ordinal is passed to the native side, keep it in sync.
+ */
+sealed class ToneMapOperator(val ordinal: Int) {
+    /** Clips at 1.0, highlights are lost. */
+    data object Clip : ToneMapOperator(0)
+
+    /** Filmic curve fitted to the ACES rendering, with a soft shoulder and toe. */
+    data object Aces : ToneMapOperator(1)
+
+    /** Reinhard extended, the luminance reaches 1.0 at the white point. */
+    data class Reinhard(val whitePoint: Float) : ToneMapOperator(2)
+
+    /** Samples evenly spaced over [0, 1], interpolated linearly and applied to each channel. */
+    class Curve(val samples: FloatArray) : ToneMapOperator(3)
+}
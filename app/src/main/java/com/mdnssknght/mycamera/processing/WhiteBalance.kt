@@ -0,0 +1,25 @@
+package com.mdnssknght.mycamera.processing
+
+/**
+ * Source of the white balance applied by the native RAW processor. The ordinal is passed to the
+ * native side, keep it in sync.
+ */
+sealed class WhiteBalance(val ordinal: Int) {
+    /** Gains and neutral point chosen by the camera AWB for the capture. */
+    data object Camera : WhiteBalance(0)
+
+    /** Estimated from the RAW statistics, assuming the average of the scene is neutral. */
+    data object GrayWorld : WhiteBalance(1)
+
+    /** Estimated from the RAW statistics, assuming the brightest unclipped values are neutral. */
+    data object WhitePatch : WhiteBalance(2)
+
+    /** Estimated from the RAW statistics, assuming the average of the edges is neutral. */
+    data object GrayEdge : WhiteBalance(3)
+
+    /** Scene illuminant in Kelvin, with a tint that is positive towards magenta. */
+    data class Temperature(val temperature: Float, val tint: Float) : WhiteBalance(4)
+
+    /** Gains of the [R, Geven, Godd, B] channels, as COLOR_CORRECTION_GAINS. */
+    class Gains(val gains: FloatArray) : WhiteBalance(5)
+}
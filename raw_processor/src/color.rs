@@ -0,0 +1,670 @@
+// Color science helpers following the DNG specification and the Adobe DNG SDK
+// (dng_color_spec.cpp and dng_temperature.cpp)
+
+pub type Matrix3 = [[f64; 3]; 3];
+pub type Vector3 = [f64; 3];
+
+// CIE XYZ of the D50 white point (profile connection space)
+pub const D50_XYZ: Vector3 = [0.9642, 1.0, 0.8249];
+pub const D50_XY: [f64; 2] = [0.3457, 0.3585];
+pub const D65_XY: [f64; 2] = [0.3127, 0.3290];
+
+// Chromaticities of the primaries and of the white point of an RGB color space
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Primaries {
+    pub red: [f64; 2],
+    pub green: [f64; 2],
+    pub blue: [f64; 2],
+    pub white: [f64; 2],
+}
+
+// IEC 61966-2-1, the primaries of ITU-R BT.709
+pub const SRGB_PRIMARIES: Primaries = Primaries {
+    red: [0.64, 0.33],
+    green: [0.30, 0.60],
+    blue: [0.15, 0.06],
+    white: D65_XY,
+};
+
+// SMPTE EG 432-1, the primaries of DCI-P3 with the D65 white point
+pub const DISPLAY_P3_PRIMARIES: Primaries = Primaries {
+    red: [0.680, 0.320],
+    green: [0.265, 0.690],
+    blue: [0.150, 0.060],
+    white: D65_XY,
+};
+
+// ITU-R BT.2020, also used by BT.2100 for PQ and HLG
+pub const REC2020_PRIMARIES: Primaries = Primaries {
+    red: [0.708, 0.292],
+    green: [0.170, 0.797],
+    blue: [0.131, 0.046],
+    white: D65_XY,
+};
+
+// ROMM RGB (ISO 22028-2), relative to D50 like the profile connection space
+pub const PROPHOTO_PRIMARIES: Primaries = Primaries {
+    red: [0.7347, 0.2653],
+    green: [0.1596, 0.8404],
+    blue: [0.0366, 0.0001],
+    white: D50_XY,
+};
+
+// Cone response domain of the Bradford chromatic adaptation
+const BRADFORD: Matrix3 = [
+    [0.8951, 0.2664, -0.1614],
+    [-0.7502, 1.7135, 0.0367],
+    [0.0389, -0.0685, 1.0296],
+];
+
+pub fn matrix_from_row_major(m: [f32; 9]) -> Matrix3 {
+    [
+        [m[0] as f64, m[1] as f64, m[2] as f64],
+        [m[3] as f64, m[4] as f64, m[5] as f64],
+        [m[6] as f64, m[7] as f64, m[8] as f64],
+    ]
+}
+
+pub fn matrix_to_row_major(m: &Matrix3) -> [f32; 9] {
+    [
+        m[0][0] as f32,
+        m[0][1] as f32,
+        m[0][2] as f32,
+        m[1][0] as f32,
+        m[1][1] as f32,
+        m[1][2] as f32,
+        m[2][0] as f32,
+        m[2][1] as f32,
+        m[2][2] as f32,
+    ]
+}
+
+pub fn diagonal(v: Vector3) -> Matrix3 {
+    [[v[0], 0.0, 0.0], [0.0, v[1], 0.0], [0.0, 0.0, v[2]]]
+}
+
+pub fn mul(a: &Matrix3, b: &Matrix3) -> Matrix3 {
+    let mut m = [[0.0; 3]; 3];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    m
+}
+
+pub fn mul_vector(a: &Matrix3, v: Vector3) -> Vector3 {
+    [
+        a[0][0] * v[0] + a[0][1] * v[1] + a[0][2] * v[2],
+        a[1][0] * v[0] + a[1][1] * v[1] + a[1][2] * v[2],
+        a[2][0] * v[0] + a[2][1] * v[1] + a[2][2] * v[2],
+    ]
+}
+
+pub fn lerp(a: &Matrix3, b: &Matrix3, weight: f64) -> Matrix3 {
+    let mut m = [[0.0; 3]; 3];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = weight * a[i][j] + (1.0 - weight) * b[i][j];
+        }
+    }
+    m
+}
+
+pub fn invert(m: &Matrix3) -> Option<Matrix3> {
+    let a = m[1][1] * m[2][2] - m[2][1] * m[1][2];
+    let b = m[2][1] * m[0][2] - m[0][1] * m[2][2];
+    let c = m[0][1] * m[1][2] - m[1][1] * m[0][2];
+
+    let determinant = m[0][0] * a + m[1][0] * b + m[2][0] * c;
+
+    if determinant.abs() < 1.0e-10 {
+        return None;
+    }
+
+    let inverse = [
+        [a, b, c],
+        [
+            m[2][0] * m[1][2] - m[1][0] * m[2][2],
+            m[0][0] * m[2][2] - m[2][0] * m[0][2],
+            m[1][0] * m[0][2] - m[0][0] * m[1][2],
+        ],
+        [
+            m[1][0] * m[2][1] - m[2][0] * m[1][1],
+            m[2][0] * m[0][1] - m[0][0] * m[2][1],
+            m[0][0] * m[1][1] - m[1][0] * m[0][1],
+        ],
+    ];
+
+    Some(inverse.map(|row| row.map(|value| value / determinant)))
+}
+
+pub fn xyz_to_xy(xyz: Vector3) -> [f64; 2] {
+    let total = xyz[0] + xyz[1] + xyz[2];
+
+    if total > 0.0 {
+        [xyz[0] / total, xyz[1] / total]
+    } else {
+        D50_XY
+    }
+}
+
+pub fn xy_to_xyz(xy: [f64; 2]) -> Vector3 {
+    // Keep the chromaticity within the spectral locus, y = 0 has no defined XYZ
+    let x = xy[0].clamp(0.000001, 0.999999);
+    let y = xy[1].clamp(0.000001, 0.999999);
+
+    let z = (1.0 - x - y).max(0.0);
+
+    [x / y, 1.0, z / y]
+}
+
+// Camera neutral white balanced by the [R, G, G, B] gains of the shifted mosaic, the greens
+// are averaged
+pub fn gains_to_neutral(gains: [f32; 4]) -> Vector3 {
+    [
+        1.0 / gains[0] as f64,
+        2.0 / (gains[1] + gains[2]) as f64,
+        1.0 / gains[3] as f64,
+    ]
+}
+
+pub fn neutral_to_gains(neutral: Vector3) -> [f32; 4] {
+    [neutral[0], neutral[1], neutral[1], neutral[2]].map(|value| (1.0 / value) as f32)
+}
+
+// Linear RGB to CIE XYZ relative to the white point of the color space
+pub fn rgb_to_xyz(primaries: &Primaries) -> Matrix3 {
+    let [red, green, blue] = [primaries.red, primaries.green, primaries.blue].map(xy_to_xyz);
+    let columns = [
+        [red[0], green[0], blue[0]],
+        [red[1], green[1], blue[1]],
+        [red[2], green[2], blue[2]],
+    ];
+
+    // Scale the primaries so that RGB white maps to the white point
+    let scale = match invert(&columns) {
+        Some(inverse) => mul_vector(&inverse, xy_to_xyz(primaries.white)),
+        None => [1.0; 3],
+    };
+
+    mul(&columns, &diagonal(scale))
+}
+
+// Bradford adaptation of CIE XYZ from one white point to another
+pub fn adapt(source_white: Vector3, destination_white: Vector3) -> Matrix3 {
+    let source = mul_vector(&BRADFORD, source_white);
+    let destination = mul_vector(&BRADFORD, destination_white);
+
+    let scale = diagonal([
+        destination[0] / source[0],
+        destination[1] / source[1],
+        destination[2] / source[2],
+    ]);
+
+    let inverse = invert(&BRADFORD).expect("Bradford matrix is invertible");
+
+    mul(&inverse, &mul(&scale, &BRADFORD))
+}
+
+// CIE XYZ D50, as produced by the forward matrices, to linear RGB of the color space
+pub fn xyz_d50_to_rgb(primaries: &Primaries) -> Matrix3 {
+    let rgb_to_xyz = rgb_to_xyz(primaries);
+    let xyz_to_rgb = invert(&rgb_to_xyz).unwrap_or(rgb_to_xyz);
+
+    if primaries.white == D50_XY {
+        return xyz_to_rgb;
+    }
+
+    mul(&xyz_to_rgb, &adapt(D50_XYZ, xy_to_xyz(primaries.white)))
+}
+
+// Contribution of the linear RGB channels of the color space to the luminance
+pub fn luminance_coefficients(primaries: &Primaries) -> Vector3 {
+    rgb_to_xyz(primaries)[1]
+}
+
+// Robertson's isotemperature lines: reciprocal temperature (mired), u, v and slope
+const TEMPERATURE_TABLE: [[f64; 4]; 31] = [
+    [0.0, 0.18006, 0.26352, -0.24341],
+    [10.0, 0.18066, 0.26589, -0.25479],
+    [20.0, 0.18133, 0.26846, -0.26876],
+    [30.0, 0.18208, 0.27119, -0.28539],
+    [40.0, 0.18293, 0.27407, -0.30470],
+    [50.0, 0.18388, 0.27709, -0.32675],
+    [60.0, 0.18494, 0.28021, -0.35156],
+    [70.0, 0.18611, 0.28342, -0.37915],
+    [80.0, 0.18740, 0.28668, -0.40955],
+    [90.0, 0.18880, 0.28997, -0.44278],
+    [100.0, 0.19032, 0.29326, -0.47888],
+    [125.0, 0.19462, 0.30141, -0.58204],
+    [150.0, 0.19962, 0.30921, -0.70471],
+    [175.0, 0.20525, 0.31647, -0.84901],
+    [200.0, 0.21142, 0.32312, -1.0182],
+    [225.0, 0.21807, 0.32909, -1.2168],
+    [250.0, 0.22511, 0.33439, -1.4512],
+    [275.0, 0.23247, 0.33904, -1.7298],
+    [300.0, 0.24010, 0.34308, -2.0637],
+    [325.0, 0.24792, 0.34655, -2.4681],
+    [350.0, 0.25591, 0.34951, -2.9641],
+    [375.0, 0.26400, 0.35200, -3.5814],
+    [400.0, 0.27218, 0.35407, -4.3633],
+    [425.0, 0.28039, 0.35577, -5.3762],
+    [450.0, 0.28863, 0.35714, -6.7262],
+    [475.0, 0.29685, 0.35823, -8.5955],
+    [500.0, 0.30505, 0.35907, -11.324],
+    [525.0, 0.31320, 0.35968, -15.628],
+    [550.0, 0.32129, 0.36011, -23.325],
+    [575.0, 0.32931, 0.36038, -40.770],
+    [600.0, 0.33724, 0.36051, -116.45],
+];
+
+const TINT_SCALE: f64 = -3000.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Temperature {
+    // Correlated color temperature in Kelvin
+    pub temperature: f64,
+    // Offset from the Planckian locus, positive values are towards magenta
+    pub tint: f64,
+}
+
+impl Temperature {
+    pub fn from_xy(xy: [f64; 2]) -> Temperature {
+        let denominator = 1.5 - xy[0] + 6.0 * xy[1];
+        let u = 2.0 * xy[0] / denominator;
+        let v = 3.0 * xy[1] / denominator;
+
+        let mut last_dt = 0.0;
+        let mut last_du = 0.0;
+        let mut last_dv = 0.0;
+
+        for index in 1..TEMPERATURE_TABLE.len() {
+            let [r0, u0, v0, _] = TEMPERATURE_TABLE[index - 1];
+            let [r1, u1, v1, t1] = TEMPERATURE_TABLE[index];
+
+            // Unit vector along the isotemperature line
+            let length = (1.0 + t1 * t1).sqrt();
+            let du = 1.0 / length;
+            let dv = t1 / length;
+
+            // Signed distance from the isotemperature line
+            let dt = -(u - u1) * dv + (v - v1) * du;
+
+            if dt <= 0.0 || index == TEMPERATURE_TABLE.len() - 1 {
+                let dt = -dt.min(0.0);
+
+                let f = if index == 1 { 0.0 } else { dt / (last_dt + dt) };
+
+                let temperature = 1.0e6 / (r0 * f + r1 * (1.0 - f));
+
+                let uu = u - (u0 * f + u1 * (1.0 - f));
+                let vv = v - (v0 * f + v1 * (1.0 - f));
+
+                let du = du * (1.0 - f) + last_du * f;
+                let dv = dv * (1.0 - f) + last_dv * f;
+                let length = (du * du + dv * dv).sqrt();
+
+                let tint = (uu * du / length + vv * dv / length) * TINT_SCALE;
+
+                return Temperature { temperature, tint };
+            }
+
+            last_dt = dt;
+            last_du = du;
+            last_dv = dv;
+        }
+
+        unreachable!()
+    }
+
+    pub fn to_xy(self) -> [f64; 2] {
+        let r = 1.0e6 / self.temperature;
+        let offset = self.tint / TINT_SCALE;
+
+        for index in 1..TEMPERATURE_TABLE.len() {
+            let [r0, u0, v0, t0] = TEMPERATURE_TABLE[index - 1];
+            let [r1, u1, v1, t1] = TEMPERATURE_TABLE[index];
+
+            if r >= r1 && index != TEMPERATURE_TABLE.len() - 1 {
+                continue;
+            }
+
+            // Point on the Planckian locus, interpolated between the isotemperature lines
+            let f = ((r1 - r) / (r1 - r0)).clamp(0.0, 1.0);
+            let u = u0 * f + u1 * (1.0 - f);
+            let v = v0 * f + v1 * (1.0 - f);
+
+            // Offset along the interpolated isotemperature line by the tint
+            let length_0 = (1.0 + t0 * t0).sqrt();
+            let length_1 = (1.0 + t1 * t1).sqrt();
+            let du = f / length_0 + (1.0 - f) / length_1;
+            let dv = f * t0 / length_0 + (1.0 - f) * t1 / length_1;
+            let length = (du * du + dv * dv).sqrt();
+
+            let u = u + du / length * offset;
+            let v = v + dv / length * offset;
+
+            let denominator = u - 4.0 * v + 2.0;
+
+            return [1.5 * u / denominator, v / denominator];
+        }
+
+        unreachable!()
+    }
+}
+
+// Temperature of the EXIF LightSource values reported by
+// CameraCharacteristics.SENSOR_REFERENCE_ILLUMINANT1 and SENSOR_REFERENCE_ILLUMINANT2
+pub fn illuminant_temperature(illuminant: i32) -> Option<f64> {
+    match illuminant {
+        1 /* DAYLIGHT */ => Some(5500.0),
+        2 /* FLUORESCENT */ => Some(4150.0),
+        3 /* TUNGSTEN */ => Some(2850.0),
+        4 /* FLASH */ => Some(5500.0),
+        9 /* FINE_WEATHER */ => Some(5500.0),
+        10 /* CLOUDY_WEATHER */ => Some(6500.0),
+        11 /* SHADE */ => Some(7500.0),
+        12 /* DAYLIGHT_FLUORESCENT */ => Some(6430.0),
+        13 /* DAY_WHITE_FLUORESCENT */ => Some(5000.0),
+        14 /* COOL_WHITE_FLUORESCENT */ => Some(4150.0),
+        15 /* WHITE_FLUORESCENT */ => Some(3450.0),
+        17 /* STANDARD_A */ => Some(2850.0),
+        18 /* STANDARD_B */ => Some(4871.0),
+        19 /* STANDARD_C */ => Some(6774.0),
+        20 /* D55 */ => Some(5500.0),
+        21 /* D65 */ => Some(6504.0),
+        22 /* D75 */ => Some(7504.0),
+        23 /* D50 */ => Some(5003.0),
+        24 /* ISO_STUDIO_TUNGSTEN */ => Some(3200.0),
+        _ => None,
+    }
+}
+
+// Camera calibration for a single reference illuminant
+#[derive(Clone, Copy, Debug)]
+pub struct Calibration {
+    pub temperature: f64,
+    // CIE XYZ to reference camera space (SENSOR_COLOR_TRANSFORM)
+    pub color_matrix: Matrix3,
+    // White balanced camera space to CIE XYZ D50 (SENSOR_FORWARD_MATRIX)
+    pub forward_matrix: Matrix3,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ColorSpec {
+    calibration_1: Calibration,
+    calibration_2: Option<Calibration>,
+}
+
+impl ColorSpec {
+    pub fn new(calibration_1: Calibration, calibration_2: Option<Calibration>) -> ColorSpec {
+        ColorSpec {
+            calibration_1: normalize_calibration(calibration_1),
+            calibration_2: calibration_2.map(normalize_calibration),
+        }
+    }
+
+    // Calibration as reported by CameraCharacteristics, the matrices are row major and those
+    // of the second illuminant are zeroed on devices calibrated for a single illuminant
+    pub fn from_characteristics(
+        reference_illuminant_1: i32,
+        reference_illuminant_2: i32,
+        color_transform_1: [f32; 9],
+        color_transform_2: [f32; 9],
+        forward_matrix_1: [f32; 9],
+        forward_matrix_2: [f32; 9],
+    ) -> ColorSpec {
+        ColorSpec::new(
+            Calibration {
+                temperature: illuminant_temperature(reference_illuminant_1).unwrap_or(5003.0),
+                color_matrix: matrix_from_row_major(color_transform_1),
+                forward_matrix: matrix_from_row_major(forward_matrix_1),
+            },
+            illuminant_temperature(reference_illuminant_2)
+                .filter(|_| color_transform_2.iter().any(|&value| value != 0.0))
+                .map(|temperature| Calibration {
+                    temperature,
+                    color_matrix: matrix_from_row_major(color_transform_2),
+                    forward_matrix: matrix_from_row_major(forward_matrix_2),
+                }),
+        )
+    }
+
+    // Weight of the first calibration for a scene illuminant of the given temperature,
+    // interpolated linearly in inverse temperature and clamped to the calibrated range
+    fn weight(&self, temperature: f64) -> f64 {
+        let Some(calibration_2) = self.calibration_2 else {
+            return 1.0;
+        };
+
+        let t1 = self.calibration_1.temperature;
+        let t2 = calibration_2.temperature;
+
+        if t1 == t2 {
+            return 1.0;
+        }
+
+        let g = (1.0 / temperature - 1.0 / t2) / (1.0 / t1 - 1.0 / t2);
+        g.clamp(0.0, 1.0)
+    }
+
+    fn interpolate(&self, temperature: f64, f: impl Fn(&Calibration) -> Matrix3) -> Matrix3 {
+        match self.calibration_2 {
+            Some(calibration_2) => lerp(
+                &f(&self.calibration_1),
+                &f(&calibration_2),
+                self.weight(temperature),
+            ),
+            None => f(&self.calibration_1),
+        }
+    }
+
+    fn xyz_to_camera(&self, temperature: f64) -> Matrix3 {
+        self.interpolate(temperature, |calibration| calibration.color_matrix)
+    }
+
+    fn forward_matrix(&self, temperature: f64) -> Matrix3 {
+        self.interpolate(temperature, |calibration| calibration.forward_matrix)
+    }
+
+    // Chromaticity of the scene illuminant given the camera neutral, found iteratively
+    // because the color matrix used to convert the neutral depends on the result
+    pub fn neutral_to_xy(&self, neutral: Vector3) -> [f64; 2] {
+        const MAX_PASSES: usize = 30;
+
+        let mut last = D50_XY;
+
+        for pass in 0..MAX_PASSES {
+            let temperature = Temperature::from_xy(last).temperature;
+
+            let Some(camera_to_xyz) = invert(&self.xyz_to_camera(temperature)) else {
+                return last;
+            };
+
+            let mut next = xyz_to_xy(mul_vector(&camera_to_xyz, neutral));
+
+            if (next[0] - last[0]).abs() + (next[1] - last[1]).abs() < 1.0e-7 {
+                return next;
+            }
+
+            // If we reach the limit without converging, we are most likely in a two value
+            // oscillation, so take the average
+            if pass == MAX_PASSES - 1 {
+                next = [(last[0] + next[0]) * 0.5, (last[1] + next[1]) * 0.5];
+            }
+
+            last = next;
+        }
+
+        last
+    }
+
+    // Camera neutral of a scene illuminant of the given chromaticity, normalized to green
+    pub fn xy_to_neutral(&self, xy: [f64; 2]) -> Vector3 {
+        let temperature = Temperature::from_xy(xy).temperature;
+        let neutral = mul_vector(&self.xyz_to_camera(temperature), xy_to_xyz(xy));
+
+        neutral.map(|value| (value / neutral[1]).max(1.0e-3))
+    }
+
+    // Gains of the shifted mosaic that neutralize a scene illuminant on or near the Planckian
+    // locus, normalized to green
+    pub fn temperature_to_gains(&self, temperature: Temperature) -> [f32; 4] {
+        neutral_to_gains(self.xy_to_neutral(temperature.to_xy()))
+    }
+
+    // Scene illuminant neutralized by the gains of the shifted mosaic, for display
+    pub fn gains_to_temperature(&self, gains: [f32; 4]) -> Temperature {
+        Temperature::from_xy(self.neutral_to_xy(gains_to_neutral(gains)))
+    }
+
+    // Camera space (not white balanced) to CIE XYZ D50 for the given camera neutral
+    pub fn camera_to_xyz_d50(&self, neutral: Vector3) -> (Matrix3, Temperature) {
+        let temperature = Temperature::from_xy(self.neutral_to_xy(neutral));
+
+        let white_balance = diagonal(neutral.map(|value| 1.0 / value));
+        let camera_to_xyz = mul(
+            &self.forward_matrix(temperature.temperature),
+            &white_balance,
+        );
+
+        (camera_to_xyz, temperature)
+    }
+}
+
+// Scale the forward matrix so that camera white maps exactly to D50
+fn normalize_calibration(calibration: Calibration) -> Calibration {
+    let white = mul_vector(&calibration.forward_matrix, [1.0, 1.0, 1.0]);
+
+    if white.iter().any(|value| value.abs() < 1.0e-10) {
+        return calibration;
+    }
+
+    let scale = diagonal([
+        D50_XYZ[0] / white[0],
+        D50_XYZ[1] / white[1],
+        D50_XYZ[2] / white[2],
+    ]);
+
+    Calibration {
+        forward_matrix: mul(&scale, &calibration.forward_matrix),
+        ..calibration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temperature_round_trips_through_xy() {
+        for temperature in (2000..=50000).step_by(500) {
+            for tint in [-30.0, -10.0, 0.0, 10.0, 30.0] {
+                let expected = Temperature {
+                    temperature: temperature as f64,
+                    tint,
+                };
+                let actual = Temperature::from_xy(expected.to_xy());
+
+                assert!(
+                    (actual.temperature - expected.temperature).abs() < 1.0,
+                    "{expected:?} came back as {actual:?}"
+                );
+                assert!(
+                    (actual.tint - expected.tint).abs() < 0.1,
+                    "{expected:?} came back as {actual:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn standard_illuminants_keep_their_xy() {
+        // Correlated color temperatures of the EXIF D65 and D50 light sources
+        for (xy, temperature) in [(D65_XY, 6504.0), (D50_XY, 5003.0)] {
+            let actual = Temperature::from_xy(xy);
+            assert!(
+                (actual.temperature - temperature).abs() < 5.0,
+                "{xy:?} is {actual:?}"
+            );
+
+            let [x, y] = actual.to_xy();
+            assert!(
+                (x - xy[0]).abs() < 1.0e-4 && (y - xy[1]).abs() < 1.0e-4,
+                "{actual:?} is {:?}",
+                [x, y]
+            );
+        }
+    }
+
+    #[test]
+    fn gains_round_trip_through_neutral() {
+        let gains = [2.1, 1.0, 1.0, 1.6];
+
+        let actual = neutral_to_gains(gains_to_neutral(gains));
+
+        for (actual, expected) in actual.iter().zip(gains) {
+            assert!((actual - expected).abs() < 1.0e-6);
+        }
+    }
+
+    fn assert_close(actual: &[f64], expected: &[f64], tolerance: f64) {
+        assert!(
+            actual
+                .iter()
+                .zip(expected)
+                .all(|(actual, expected)| (actual - expected).abs() < tolerance),
+            "{actual:?} is not {expected:?}"
+        );
+    }
+
+    #[test]
+    fn srgb_matches_reference_matrices() {
+        // Bruce Lindbloom's Bradford adapted sRGB matrix, computed with slightly different
+        // white points than ICC D50
+        let expected = [
+            [3.1338561, -1.6168667, -0.4906146],
+            [-0.9787684, 1.9161415, 0.0334540],
+            [0.0719453, -0.2289914, 1.4052427],
+        ];
+
+        let actual = xyz_d50_to_rgb(&SRGB_PRIMARIES);
+
+        for (actual, expected) in actual.iter().zip(&expected) {
+            assert_close(actual, expected, 2.0e-3);
+        }
+
+        // ITU-R BT.709 luma coefficients
+        assert_close(
+            &luminance_coefficients(&SRGB_PRIMARIES),
+            &[0.2126, 0.7152, 0.0722],
+            1.0e-4,
+        );
+    }
+
+    #[test]
+    fn adapts_white_points() {
+        let d65 = xy_to_xyz(D65_XY);
+
+        assert_close(&mul_vector(&adapt(d65, D50_XYZ), d65), &D50_XYZ, 1.0e-9);
+        assert_close(&mul_vector(&adapt(D50_XYZ, d65), D50_XYZ), &d65, 1.0e-9);
+    }
+
+    #[test]
+    fn camera_neutral_maps_to_d50() {
+        let calibration = Calibration {
+            temperature: 6504.0,
+            color_matrix: [[0.9, -0.3, -0.1], [-0.4, 1.3, 0.1], [-0.1, 0.2, 0.6]],
+            // Rows do not add up to D50, ColorSpec rescales them
+            forward_matrix: [[0.7, 0.2, 0.1], [0.3, 0.8, -0.1], [0.0, -0.1, 0.9]],
+        };
+        let color_spec = ColorSpec::new(calibration, None);
+
+        for neutral in [[0.5, 1.0, 0.6], [0.4, 1.0, 0.8], [1.0, 1.0, 1.0]] {
+            let (camera_to_xyz, _) = color_spec.camera_to_xyz_d50(neutral);
+
+            assert_close(&mul_vector(&camera_to_xyz, neutral), &D50_XYZ, 1.0e-9);
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use std::{error::Error, fmt, io};
+
+use vulkano::{
+    LoadingError, Validated, ValidationError, VulkanError, buffer::AllocateBufferError,
+    command_buffer::CommandBufferExecError, device::DeviceFeatures, image::AllocateImageError,
+    pipeline::layout::IntoPipelineLayoutCreateInfoError, sync::HostAccessError,
+};
+
+use crate::validation::InvalidInput;
+
+#[derive(Debug)]
+pub enum RawProcessorError {
+    // Vulkan loader could not be found or initialized
+    Loading(LoadingError),
+    // No physical device matching the selection exposes a queue family with compute support
+    NoSuitableDevice,
+    // The selected device lacks features the pipeline shaders depend on
+    MissingFeatures(Box<DeviceFeatures>),
+    // A compiled shader module does not export the expected entry point
+    MissingEntryPoint,
+    // Frame or capture metadata rejected before processing
+    InvalidInput(InvalidInput),
+
+    Vulkan(VulkanError),
+    Validation(Box<ValidationError>),
+    BufferAllocation(AllocateBufferError),
+    ImageAllocation(AllocateImageError),
+    CommandBufferExecution(CommandBufferExecError),
+    HostAccess(HostAccessError),
+    PipelineLayout(IntoPipelineLayoutCreateInfoError),
+
+    Io(io::Error),
+    Jni(jni::errors::Error),
+}
+
+impl fmt::Display for RawProcessorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RawProcessorError::Loading(err) => write!(f, "failed to load Vulkan library: {err}"),
+            RawProcessorError::NoSuitableDevice => {
+                write!(
+                    f,
+                    "no matching physical device with a compute queue was found"
+                )
+            }
+            RawProcessorError::MissingFeatures(features) => {
+                write!(f, "device does not support required features: {features:?}")
+            }
+            RawProcessorError::MissingEntryPoint => {
+                write!(f, "shader module is missing its entry point")
+            }
+            RawProcessorError::InvalidInput(err) => write!(f, "invalid input: {err}"),
+            RawProcessorError::Vulkan(err) => write!(f, "Vulkan error: {err}"),
+            RawProcessorError::Validation(err) => write!(f, "Vulkan validation error: {err}"),
+            RawProcessorError::BufferAllocation(err) => {
+                write!(f, "failed to allocate buffer: {err}")
+            }
+            RawProcessorError::ImageAllocation(err) => write!(f, "failed to allocate image: {err}"),
+            RawProcessorError::CommandBufferExecution(err) => {
+                write!(f, "failed to execute command buffer: {err}")
+            }
+            RawProcessorError::HostAccess(err) => {
+                write!(f, "failed to access buffer from the host: {err}")
+            }
+            RawProcessorError::PipelineLayout(err) => {
+                write!(f, "failed to create pipeline layout: {err}")
+            }
+            RawProcessorError::Io(err) => write!(f, "I/O error: {err}"),
+            RawProcessorError::Jni(err) => write!(f, "JNI error: {err}"),
+        }
+    }
+}
+
+impl Error for RawProcessorError {}
+
+impl From<LoadingError> for RawProcessorError {
+    fn from(err: LoadingError) -> Self {
+        RawProcessorError::Loading(err)
+    }
+}
+
+impl From<InvalidInput> for RawProcessorError {
+    fn from(err: InvalidInput) -> Self {
+        RawProcessorError::InvalidInput(err)
+    }
+}
+
+impl From<VulkanError> for RawProcessorError {
+    fn from(err: VulkanError) -> Self {
+        RawProcessorError::Vulkan(err)
+    }
+}
+
+impl From<Box<ValidationError>> for RawProcessorError {
+    fn from(err: Box<ValidationError>) -> Self {
+        RawProcessorError::Validation(err)
+    }
+}
+
+impl<E> From<Validated<E>> for RawProcessorError
+where
+    RawProcessorError: From<E>,
+{
+    fn from(err: Validated<E>) -> Self {
+        match err {
+            Validated::Error(err) => err.into(),
+            Validated::ValidationError(err) => err.into(),
+        }
+    }
+}
+
+impl From<AllocateBufferError> for RawProcessorError {
+    fn from(err: AllocateBufferError) -> Self {
+        RawProcessorError::BufferAllocation(err)
+    }
+}
+
+impl From<AllocateImageError> for RawProcessorError {
+    fn from(err: AllocateImageError) -> Self {
+        RawProcessorError::ImageAllocation(err)
+    }
+}
+
+impl From<CommandBufferExecError> for RawProcessorError {
+    fn from(err: CommandBufferExecError) -> Self {
+        RawProcessorError::CommandBufferExecution(err)
+    }
+}
+
+impl From<HostAccessError> for RawProcessorError {
+    fn from(err: HostAccessError) -> Self {
+        RawProcessorError::HostAccess(err)
+    }
+}
+
+impl From<IntoPipelineLayoutCreateInfoError> for RawProcessorError {
+    fn from(err: IntoPipelineLayoutCreateInfoError) -> Self {
+        RawProcessorError::PipelineLayout(err)
+    }
+}
+
+impl From<io::Error> for RawProcessorError {
+    fn from(err: io::Error) -> Self {
+        RawProcessorError::Io(err)
+    }
+}
+
+impl From<jni::errors::Error> for RawProcessorError {
+    fn from(err: jni::errors::Error) -> Self {
+        RawProcessorError::Jni(err)
+    }
+}
@@ -1,20 +1,66 @@
-use std::{panic, slice};
+use std::{
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    ptr, slice, thread,
+};
 
 use android_logger::Config;
 use jni::{
     JNIEnv,
-    objects::{JByteArray, JByteBuffer, JClass, JFloatArray, JIntArray},
-    sys::{jbyte, jint, jlong},
+    objects::{
+        JByteArray, JByteBuffer, JClass, JFloatArray, JIntArray, JObject, JObjectArray,
+        JPrimitiveArray, JString, JValue, TypeArray,
+    },
+    sys::{jbyte, jfloat, jfloatArray, jint, jlong},
 };
 use log::{LevelFilter, error, info};
 use vulkano::VulkanLibrary;
 
+mod color;
+mod error;
 mod pipeline;
+mod processor;
+mod validation;
+
+pub use color::{ColorSpec, Temperature};
+pub use error::RawProcessorError;
+pub use pipeline::{ContextCreateInfo, DeviceCapabilities, DeviceSelection};
+pub use processor::{
+    ColorSpace, DemosaicAlgorithm, HighlightMode, LensShadingMap, OutputFormat, ProcessedImage,
+    ProcessingJob, ProcessingParams, Processor, RawFormat, RawImage, ToneMapOperator, WhiteBalance,
+};
+pub use validation::InvalidInput;
+
+const PACKAGE: &str = "com/mdnssknght/mycamera/processing";
+const EXCEPTION_CLASS: &str = "com/mdnssknght/mycamera/processing/RawProcessorException";
+
+// Runs the body of a JNI entry point, rethrowing errors and panics as a Java exception
+// since unwinding into the JVM would abort the whole application
+fn throw_on_error<T>(
+    env: &mut JNIEnv,
+    default: T,
+    body: impl FnOnce(&mut JNIEnv) -> Result<T, RawProcessorError>,
+) -> T {
+    let message = match panic::catch_unwind(AssertUnwindSafe(|| body(env))) {
+        Ok(Ok(value)) => return value,
+        Ok(Err(err)) => err.to_string(),
+        Err(_) => String::from("panic occurred in native code"),
+    };
+
+    error!("{message}");
+
+    if env.throw_new(EXCEPTION_CLASS, &message).is_err() {
+        error!("failed to throw {EXCEPTION_CLASS}");
+    }
+
+    default
+}
 
 #[unsafe(no_mangle)]
 pub extern "system" fn Java_com_mdnssknght_mycamera_processing_NativeRawProcessor_00024Companion_nativeInit(
-    _: JNIEnv,
+    mut env: JNIEnv,
     _: JClass,
+    pipeline_cache_path: JString,
 ) -> jlong {
     android_logger::init_once(
         Config::default()
@@ -44,14 +90,30 @@ pub extern "system" fn Java_com_mdnssknght_mycamera_processing_NativeRawProcesso
 
     info!("Hello, from Rust!");
 
-    let library = VulkanLibrary::new().expect("Failed to find local Vulkan library");
+    throw_on_error(&mut env, 0, |env| {
+        let pipeline_cache_path = if pipeline_cache_path.is_null() {
+            None
+        } else {
+            Some(PathBuf::from(String::from(
+                env.get_string(&pipeline_cache_path)?,
+            )))
+        };
+
+        let library = VulkanLibrary::new()?;
 
-    //
-    // Initialized only once for the entire application lifetime
-    //
-    let pipeline_context = pipeline::Context::new(library);
+        //
+        // Initialized only once for the entire application lifetime
+        //
+        let processor = Box::new(Processor::new(
+            library,
+            ContextCreateInfo {
+                pipeline_cache_path,
+                ..Default::default()
+            },
+        )?);
 
-    Box::into_raw(pipeline_context) as jlong
+        Ok(Box::into_raw(processor) as jlong)
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -60,98 +122,629 @@ pub extern "system" fn Java_com_mdnssknght_mycamera_processing_NativeRawProcesso
     _: JClass,
     handle: jlong,
 ) {
-    drop(unsafe { Box::from_raw(handle as *mut pipeline::Context) });
+    if handle != 0 {
+        let processor = unsafe { Box::from_raw(handle as *mut Processor) };
+
+        if let Err(err) = processor.save_pipeline_cache() {
+            error!("Failed to save pipeline cache: {err}");
+        }
+    }
 }
 
 #[unsafe(no_mangle)]
-pub extern "system" fn Java_com_mdnssknght_mycamera_processing_NativeRawProcessor_00024Companion_nativeProcess(
-    env: JNIEnv,
+pub extern "system" fn Java_com_mdnssknght_mycamera_processing_NativeRawProcessor_00024Companion_nativeTrim(
+    _: JNIEnv,
     _: JClass,
     handle: jlong,
-    width: jint,
-    height: jint,
-    data: JByteBuffer,
-    out: JByteArray,
-    color_filter_arrangement: jint,
-    white_level: jint,
-    black_level: JIntArray,
-    neutral_point: JFloatArray,
-    color_gains: JFloatArray,
-    color_correction_transform: JFloatArray,
-    forward_matrix_1: JFloatArray,
-    forward_matrix_2: JFloatArray,
 ) {
-    let context = unsafe { &*(handle as *const pipeline::Context) };
+    if handle != 0 {
+        let processor = unsafe { &*(handle as *const Processor) };
+        processor.trim();
+    }
+}
+
+// Reads a fixed number of elements from a JNI array, rejecting null and short arrays instead
+// of letting the JVM throw an ArrayIndexOutOfBoundsException
+fn check_array_length<T: TypeArray>(
+    env: &JNIEnv,
+    array: &JPrimitiveArray<T>,
+    name: &'static str,
+    expected: usize,
+) -> Result<(), RawProcessorError> {
+    if array.is_null() {
+        return Err(InvalidInput::NullArgument(name).into());
+    }
+
+    let actual = env.get_array_length(array)? as usize;
+    if actual < expected {
+        return Err(InvalidInput::ArrayLength {
+            name,
+            expected,
+            actual,
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+fn get_int_array<const N: usize>(
+    env: &JNIEnv,
+    array: &JIntArray,
+    name: &'static str,
+) -> Result<[i32; N], RawProcessorError> {
+    check_array_length(env, array, name, N)?;
+
+    let mut data = [0i32; N];
+    env.get_int_array_region(array, 0, &mut data)?;
+    Ok(data)
+}
+
+fn get_float_array<const N: usize>(
+    env: &JNIEnv,
+    array: &JFloatArray,
+    name: &'static str,
+) -> Result<[f32; N], RawProcessorError> {
+    check_array_length(env, array, name, N)?;
+
+    let mut data = [0f32; N];
+    env.get_float_array_region(array, 0, &mut data)?;
+    Ok(data)
+}
+
+// Fields of the Kotlin RawFrame and ProcessingParams classes are read by name and type, a
+// mismatch fails with NoSuchFieldError instead of silently reading another argument
+fn get_int_field(env: &mut JNIEnv, object: &JObject, name: &str) -> Result<i32, RawProcessorError> {
+    Ok(env.get_field(object, name, "I")?.i()?)
+}
+
+// Sizes and coordinates are Java ints, negative values would wrap around when cast
+fn unsigned(name: &'static str, value: i32) -> Result<u32, InvalidInput> {
+    u32::try_from(value).map_err(|_| InvalidInput::NegativeArgument { name, value })
+}
+
+fn get_unsigned_field(
+    env: &mut JNIEnv,
+    object: &JObject,
+    name: &'static str,
+) -> Result<u32, RawProcessorError> {
+    Ok(unsigned(name, get_int_field(env, object, name)?)?)
+}
+
+fn get_float_field(
+    env: &mut JNIEnv,
+    object: &JObject,
+    name: &str,
+) -> Result<f32, RawProcessorError> {
+    Ok(env.get_field(object, name, "F")?.f()?)
+}
+
+fn get_object_field<'local>(
+    env: &mut JNIEnv<'local>,
+    object: &JObject,
+    name: &str,
+    signature: &str,
+) -> Result<JObject<'local>, RawProcessorError> {
+    Ok(env.get_field(object, name, signature)?.l()?)
+}
 
-    let black_level = {
-        let mut data = [0i32; 4];
-        env.get_int_array_region(black_level, 0, &mut data).unwrap();
-        data
+// Ordinal of a field holding one of the enums, or of the sealed classes carrying their own
+// ordinal, of the processing package
+fn get_ordinal_field(
+    env: &mut JNIEnv,
+    object: &JObject,
+    name: &'static str,
+    class: &str,
+) -> Result<i32, RawProcessorError> {
+    let value = get_object_field(env, object, name, &format!("L{PACKAGE}/{class};"))?;
+    if value.is_null() {
+        return Err(InvalidInput::NullArgument(name).into());
+    }
+
+    // Kotlin enums inherit ordinal() from java.lang.Enum, the sealed classes have a property
+    let method = if env.is_instance_of(&value, "java/lang/Enum")? {
+        "ordinal"
+    } else {
+        "getOrdinal"
     };
 
-    let neutral_point = {
-        let mut data = [0f32; 3];
-        env.get_float_array_region(neutral_point, 0, &mut data)
-            .unwrap();
-        data
+    Ok(env.call_method(&value, method, "()I", &[])?.i()?)
+}
+
+fn get_int_array_field<const N: usize>(
+    env: &mut JNIEnv,
+    object: &JObject,
+    name: &'static str,
+) -> Result<[i32; N], RawProcessorError> {
+    let array = JIntArray::from(get_object_field(env, object, name, "[I")?);
+    get_int_array::<N>(env, &array, name)
+}
+
+fn get_float_array_field<const N: usize>(
+    env: &mut JNIEnv,
+    object: &JObject,
+    name: &'static str,
+) -> Result<[f32; N], RawProcessorError> {
+    let array = JFloatArray::from(get_object_field(env, object, name, "[F")?);
+    get_float_array::<N>(env, &array, name)
+}
+
+// Whole array of any length, None when the field is null
+fn get_float_vec_field(
+    env: &mut JNIEnv,
+    object: &JObject,
+    name: &str,
+) -> Result<Option<Vec<f32>>, RawProcessorError> {
+    let array = JFloatArray::from(get_object_field(env, object, name, "[F")?);
+    if array.is_null() {
+        return Ok(None);
+    }
+
+    let mut data = vec![0f32; env.get_array_length(&array)? as usize];
+    env.get_float_array_region(&array, 0, &mut data)?;
+    Ok(Some(data))
+}
+
+fn get_int_vec_field(
+    env: &mut JNIEnv,
+    object: &JObject,
+    name: &str,
+) -> Result<Option<Vec<i32>>, RawProcessorError> {
+    let array = JIntArray::from(get_object_field(env, object, name, "[I")?);
+    if array.is_null() {
+        return Ok(None);
+    }
+
+    let mut data = vec![0i32; env.get_array_length(&array)? as usize];
+    env.get_int_array_region(&array, 0, &mut data)?;
+    Ok(Some(data))
+}
+
+// Reads a RawFrame, its direct buffer has to outlive the returned image
+fn get_raw_image<'a>(env: &mut JNIEnv, frame: &JObject) -> Result<RawImage<'a>, RawProcessorError> {
+    if frame.is_null() {
+        return Err(InvalidInput::NullArgument("frame").into());
+    }
+
+    let data = JByteBuffer::from(get_object_field(
+        env,
+        frame,
+        "data",
+        "Ljava/nio/ByteBuffer;",
+    )?);
+    if data.is_null() {
+        return Err(InvalidInput::NullArgument("data").into());
+    }
+
+    let format = get_int_field(env, frame, "format")?;
+
+    Ok(RawImage {
+        data: unsafe {
+            slice::from_raw_parts(
+                env.get_direct_buffer_address(&data)?,
+                env.get_direct_buffer_capacity(&data)?,
+            )
+        },
+        format: RawFormat::from_image_format(format).ok_or(InvalidInput::Format(format))?,
+        width: get_unsigned_field(env, frame, "width")?,
+        height: get_unsigned_field(env, frame, "height")?,
+        row_stride: get_unsigned_field(env, frame, "rowStride")?,
+        pixel_stride: get_unsigned_field(env, frame, "pixelStride")?,
+        color_filter_arrangement: get_int_field(env, frame, "colorFilterArrangement")?,
+        white_level: get_int_field(env, frame, "whiteLevel")?,
+        black_level: get_int_array_field::<4>(env, frame, "blackLevel")?,
+    })
+}
+
+// Reads a ProcessingParams, shared by the blocking, asynchronous and burst entry points
+fn get_processing_params(
+    env: &mut JNIEnv,
+    params: &JObject,
+) -> Result<ProcessingParams, RawProcessorError> {
+    if params.is_null() {
+        return Err(InvalidInput::NullArgument("params").into());
+    }
+
+    // Explicit white balances carry their temperature and tint, or their gains
+    let white_balance_ordinal = get_ordinal_field(env, params, "whiteBalance", "WhiteBalance")?;
+    let white_balance_object = get_object_field(
+        env,
+        params,
+        "whiteBalance",
+        &format!("L{PACKAGE}/WhiteBalance;"),
+    )?;
+    let (temperature, tint, white_balance_gains) = match white_balance_ordinal {
+        4 => (
+            get_float_field(env, &white_balance_object, "temperature")?,
+            get_float_field(env, &white_balance_object, "tint")?,
+            [0.0; 4],
+        ),
+        5 => (
+            0.0,
+            0.0,
+            get_float_array_field::<4>(env, &white_balance_object, "gains")?,
+        ),
+        _ => (0.0, 0.0, [0.0; 4]),
     };
 
-    let color_gains = {
-        let mut data = [0f32; 4];
-        env.get_float_array_region(color_gains, 0, &mut data)
-            .unwrap();
-        data
+    // Only the Reinhard and curve operators carry parameters
+    let tone_map_ordinal = get_ordinal_field(env, params, "toneMapOperator", "ToneMapOperator")?;
+    let tone_map_object = get_object_field(
+        env,
+        params,
+        "toneMapOperator",
+        &format!("L{PACKAGE}/ToneMapOperator;"),
+    )?;
+    let white_point = match tone_map_ordinal {
+        2 => get_float_field(env, &tone_map_object, "whitePoint")?,
+        _ => 1.0,
+    };
+    let tone_curve = match tone_map_ordinal {
+        3 => get_float_vec_field(env, &tone_map_object, "samples")?.unwrap_or_default(),
+        _ => vec![],
     };
 
-    let color_correction_transform = {
-        let mut data = [0f32; 9];
-        env.get_float_array_region(color_correction_transform, 0, &mut data)
-            .unwrap();
-        data
+    // Null when the capture did not enable STATISTICS_LENS_SHADING_MAP_MODE
+    let lens_shading_map = match get_float_vec_field(env, params, "lensShadingGains")? {
+        Some(gains) => Some(LensShadingMap {
+            columns: get_unsigned_field(env, params, "lensShadingColumns")?,
+            rows: get_unsigned_field(env, params, "lensShadingRows")?,
+            gains,
+        }),
+        None => None,
     };
 
-    let forward_matrix_1 = {
-        let mut data = [0f32; 9];
-        env.get_float_array_region(forward_matrix_1, 0, &mut data)
-            .unwrap();
-        data
+    // Coordinates of the static defects as x, y pairs, null when there are none
+    let bad_pixels = match get_int_vec_field(env, params, "badPixelCoordinates")? {
+        Some(coordinates) => {
+            if !coordinates.len().is_multiple_of(2) {
+                return Err(InvalidInput::BadPixels(coordinates.len()).into());
+            }
+
+            coordinates
+                .chunks_exact(2)
+                .map(|point| {
+                    Ok([
+                        unsigned("badPixels", point[0])?,
+                        unsigned("badPixels", point[1])?,
+                    ])
+                })
+                .collect::<Result<_, InvalidInput>>()?
+        }
+        None => vec![],
     };
 
-    let forward_matrix_2 = {
-        let mut data = [0f32; 9];
-        env.get_float_array_region(forward_matrix_2, 0, &mut data)
-            .unwrap();
-        data
+    // (S, O) pairs of each CFA channel, null when the device does not report a noise profile
+    let noise_profile = {
+        let array = JFloatArray::from(get_object_field(
+            env,
+            params,
+            "noiseProfileCoefficients",
+            "[F",
+        )?);
+
+        if array.is_null() {
+            None
+        } else {
+            let coefficients = get_float_array::<8>(env, &array, "noiseProfile")?;
+
+            Some(
+                [0, 1, 2, 3]
+                    .map(|channel| [coefficients[channel * 2], coefficients[channel * 2 + 1]]),
+            )
+        }
     };
 
-    let mut finish = pipeline::Finish::new();
-
-    finish.finish(
-        &context,
-        env.get_direct_buffer_address(&data).unwrap(),
-        env.get_direct_buffer_capacity(&data).unwrap(),
-        [width, height],
-        color_filter_arrangement,
-        white_level,
-        black_level,
-        neutral_point,
-        color_gains,
-        color_correction_transform,
+    let demosaic_algorithm =
+        get_ordinal_field(env, params, "demosaicAlgorithm", "DemosaicAlgorithm")?;
+    let highlight_mode = get_ordinal_field(env, params, "highlightMode", "HighlightMode")?;
+    let color_space = get_ordinal_field(env, params, "colorSpace", "ColorSpace")?;
+    let output_format = get_ordinal_field(env, params, "outputFormat", "OutputFormat")?;
+
+    Ok(ProcessingParams {
+        neutral_point: get_float_array_field::<3>(env, params, "neutralPoint")?,
+        color_gains: get_float_array_field::<4>(env, params, "colorGains")?,
+        white_balance: WhiteBalance::from_ordinal(
+            white_balance_ordinal,
+            temperature,
+            tint,
+            white_balance_gains,
+        )
+        .ok_or(InvalidInput::WhiteBalance(white_balance_ordinal))?,
+        reference_illuminant_1: get_int_field(env, params, "referenceIlluminant1")?,
+        reference_illuminant_2: get_int_field(env, params, "referenceIlluminant2")?,
+        color_transform_1: get_float_array_field::<9>(env, params, "colorTransform1")?,
+        color_transform_2: get_float_array_field::<9>(env, params, "colorTransform2")?,
+        forward_matrix_1: get_float_array_field::<9>(env, params, "forwardMatrix1")?,
+        forward_matrix_2: get_float_array_field::<9>(env, params, "forwardMatrix2")?,
+        demosaic_algorithm: DemosaicAlgorithm::from_ordinal(demosaic_algorithm)
+            .ok_or(InvalidInput::DemosaicAlgorithm(demosaic_algorithm))?,
+        highlight_mode: HighlightMode::from_ordinal(highlight_mode)
+            .ok_or(InvalidInput::HighlightMode(highlight_mode))?,
+        lens_shading_map,
+        defect_threshold: get_float_field(env, params, "defectThreshold")?,
+        bad_pixels,
+        noise_profile,
+        denoise_strength: get_float_field(env, params, "denoiseStrength")?,
+        local_tone_map_strength: get_float_field(env, params, "localToneMapStrength")?,
+        synthetic_exposures: get_unsigned_field(env, params, "syntheticExposures")?,
+        tone_map_operator: ToneMapOperator::from_ordinal(tone_map_ordinal, white_point, tone_curve)
+            .ok_or(InvalidInput::ToneMapOperator(tone_map_ordinal))?,
+        exposure_bias: get_float_field(env, params, "exposureBias")?,
+        color_space: ColorSpace::from_ordinal(color_space)
+            .ok_or(InvalidInput::ColorSpace(color_space))?,
+        output_format: OutputFormat::from_ordinal(output_format)
+            .ok_or(InvalidInput::OutputFormat(output_format))?,
+    })
+}
+
+// Reads the frames and the parameters passed from Kotlin and submits them. Frames of a burst
+// are merged into the first one.
+fn submit_frames(
+    env: &mut JNIEnv,
+    handle: jlong,
+    frames: &[JObject],
+    params: &JObject,
+) -> Result<ProcessingJob, RawProcessorError> {
+    let processor = unsafe { &*(handle as *const Processor) };
+
+    let frames = frames
+        .iter()
+        .map(|frame| get_raw_image(env, frame))
+        .collect::<Result<Vec<_>, RawProcessorError>>()?;
+
+    let params = get_processing_params(env, params)?;
+
+    processor.submit_burst(&frames, &params)
+}
+
+// Hands a submitted job over to a worker thread attached to the JVM
+fn complete_on_worker(
+    env: &mut JNIEnv,
+    callback: JObject,
+    job: ProcessingJob,
+) -> Result<(), RawProcessorError> {
+    let vm = env.get_java_vm()?;
+    let callback = env.new_global_ref(callback)?;
+
+    thread::Builder::new()
+        .name(String::from("RawProcessorJob"))
+        .spawn(move || {
+            let result = vm
+                .attach_current_thread()
+                .map_err(RawProcessorError::from)
+                .and_then(|mut env| complete_job(&mut env, callback.as_obj(), job));
+
+            if let Err(err) = result {
+                error!("Failed to deliver processed image: {err}");
+            }
+        })?;
+
+    Ok(())
+}
+
+// Waits for a submitted job on a worker thread and hands the output, or the error, to the
+// RawProcessorCallback
+fn complete_job(
+    env: &mut JNIEnv,
+    callback: &JObject,
+    job: ProcessingJob,
+) -> Result<(), RawProcessorError> {
+    let output = job
+        .wait()
+        .and_then(|output| Ok(env.byte_array_from_slice(&output.read()?)?));
+
+    let result = match output {
+        Ok(array) => env.call_method(callback, "onProcessed", "([B)V", &[JValue::Object(&array)]),
+        Err(err) => {
+            error!("{err}");
+
+            let message = env.new_string(err.to_string())?;
+            let exception = env.new_object(
+                EXCEPTION_CLASS,
+                "(Ljava/lang/String;)V",
+                &[JValue::Object(&message)],
+            )?;
+
+            env.call_method(
+                callback,
+                "onError",
+                format!("(L{EXCEPTION_CLASS};)V"),
+                &[JValue::Object(&exception)],
+            )
+        }
+    };
+
+    // Exceptions thrown by the callback have nowhere to propagate to on this thread
+    if result.is_err() && env.exception_check()? {
+        env.exception_describe()?;
+        env.exception_clear()?;
+    }
+
+    result?;
+
+    Ok(())
+}
+
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_mdnssknght_mycamera_processing_NativeRawProcessor_00024Companion_nativeProcess(
+    mut env: JNIEnv,
+    _: JClass,
+    handle: jlong,
+    frame: JObject,
+    params: JObject,
+    out: JByteArray,
+) {
+    throw_on_error(&mut env, (), |env| {
+        let job = submit_frames(env, handle, &[frame], &params)?;
+
+        let output = job.wait()?;
+
+        let output_buffer = output.read()?;
+        let output_buffer = unsafe {
+            slice::from_raw_parts(output_buffer.as_ptr() as *const jbyte, output_buffer.len())
+        };
+
+        env.set_byte_array_region(out, 0, output_buffer)?;
+
+        info!("Command buffer execution succeeded");
+
+        Ok(())
+    })
+}
+
+// Returns once the frame has been submitted, the callback is invoked from a worker thread
+// when the output is ready so that the caller can already queue the next frame
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_mdnssknght_mycamera_processing_NativeRawProcessor_00024Companion_nativeSubmit(
+    mut env: JNIEnv,
+    _: JClass,
+    handle: jlong,
+    frame: JObject,
+    params: JObject,
+    callback: JObject,
+) {
+    throw_on_error(&mut env, (), |env| {
+        let job = submit_frames(env, handle, &[frame], &params)?;
+
+        complete_on_worker(env, callback, job)
+    })
+}
+
+// Aligns and merges a burst of RAW frames into the first one. The callback is invoked as for
+// nativeSubmit.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_mdnssknght_mycamera_processing_NativeRawProcessor_00024Companion_nativeSubmitBurst(
+    mut env: JNIEnv,
+    _: JClass,
+    handle: jlong,
+    frames: JObjectArray,
+    params: JObject,
+    callback: JObject,
+) {
+    throw_on_error(&mut env, (), |env| {
+        if frames.is_null() {
+            return Err(InvalidInput::NullArgument("frames").into());
+        }
+
+        let frames = (0..env.get_array_length(&frames)?)
+            .map(|index| env.get_object_array_element(&frames, index))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let job = submit_frames(env, handle, &frames, &params)?;
+
+        complete_on_worker(env, callback, job)
+    })
+}
+
+// Color calibration of the camera, passed as in ProcessingParams
+fn get_color_spec(
+    env: &JNIEnv,
+    reference_illuminant_1: jint,
+    reference_illuminant_2: jint,
+    color_transform_1: JFloatArray,
+    color_transform_2: JFloatArray,
+    forward_matrix_1: JFloatArray,
+    forward_matrix_2: JFloatArray,
+) -> Result<ColorSpec, RawProcessorError> {
+    let color_transform_1 = get_float_array::<9>(env, &color_transform_1, "colorTransform1")?;
+    let color_transform_2 = get_float_array::<9>(env, &color_transform_2, "colorTransform2")?;
+    let forward_matrix_1 = get_float_array::<9>(env, &forward_matrix_1, "forwardMatrix1")?;
+    let forward_matrix_2 = get_float_array::<9>(env, &forward_matrix_2, "forwardMatrix2")?;
+
+    validation::validate_calibration(
+        color_transform_1,
+        color_transform_2,
         forward_matrix_1,
         forward_matrix_2,
-    );
+    )?;
+
+    Ok(ColorSpec::from_characteristics(
+        reference_illuminant_1,
+        reference_illuminant_2,
+        color_transform_1,
+        color_transform_2,
+        forward_matrix_1,
+        forward_matrix_2,
+    ))
+}
+
+// Gains of the [R, G, G, B] channels that neutralize a scene illuminant of the given
+// temperature and tint, as passed for an explicit white balance
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_mdnssknght_mycamera_processing_NativeRawProcessor_00024Companion_nativeTemperatureToGains(
+    mut env: JNIEnv,
+    _: JClass,
+    temperature: jfloat,
+    tint: jfloat,
+    reference_illuminant_1: jint,
+    reference_illuminant_2: jint,
+    color_transform_1: JFloatArray,
+    color_transform_2: JFloatArray,
+    forward_matrix_1: JFloatArray,
+    forward_matrix_2: JFloatArray,
+) -> jfloatArray {
+    throw_on_error(&mut env, ptr::null_mut(), |env| {
+        let color_spec = get_color_spec(
+            env,
+            reference_illuminant_1,
+            reference_illuminant_2,
+            color_transform_1,
+            color_transform_2,
+            forward_matrix_1,
+            forward_matrix_2,
+        )?;
+
+        validation::validate_temperature(temperature, tint)?;
+
+        let gains = color_spec.temperature_to_gains(Temperature {
+            temperature: temperature as f64,
+            tint: tint as f64,
+        });
+
+        let array = env.new_float_array(gains.len() as jint)?;
+        env.set_float_array_region(&array, 0, &gains)?;
 
-    let output_buffer = match finish.get_buffer_output() {
-        Some(buffer_guard) => {
-            let buffer = buffer_guard
-                .read()
-                .expect("Failed to lock buffer for reading");
-            unsafe { slice::from_raw_parts(buffer.as_ptr() as *const jbyte, buffer.len()) }
+        Ok(array.into_raw())
+    })
+}
+
+// Temperature and tint of the scene illuminant neutralized by the gains of the [R, G, G, B]
+// channels, such as COLOR_CORRECTION_GAINS, for display
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_mdnssknght_mycamera_processing_NativeRawProcessor_00024Companion_nativeGainsToTemperature(
+    mut env: JNIEnv,
+    _: JClass,
+    gains: JFloatArray,
+    reference_illuminant_1: jint,
+    reference_illuminant_2: jint,
+    color_transform_1: JFloatArray,
+    color_transform_2: JFloatArray,
+    forward_matrix_1: JFloatArray,
+    forward_matrix_2: JFloatArray,
+) -> jfloatArray {
+    throw_on_error(&mut env, ptr::null_mut(), |env| {
+        let color_spec = get_color_spec(
+            env,
+            reference_illuminant_1,
+            reference_illuminant_2,
+            color_transform_1,
+            color_transform_2,
+            forward_matrix_1,
+            forward_matrix_2,
+        )?;
+
+        let gains = get_float_array::<4>(env, &gains, "gains")?;
+        if gains.iter().any(|gain| !gain.is_finite() || *gain <= 0.0) {
+            return Err(InvalidInput::ColorGains(gains).into());
         }
-        _ => panic!("Something went wrong"),
-    };
 
-    env.set_byte_array_region(out, 0, output_buffer).unwrap();
+        let temperature = color_spec.gains_to_temperature(gains);
+        let values = [temperature.temperature as f32, temperature.tint as f32];
+
+        let array = env.new_float_array(values.len() as jint)?;
+        env.set_float_array_region(&array, 0, &values)?;
 
-    info!("Command buffer execution succeeded");
+        Ok(array.into_raw())
+    })
 }
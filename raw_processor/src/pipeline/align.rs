@@ -0,0 +1,346 @@
+use std::sync::Arc;
+
+use vulkano::{
+    buffer::BufferContents,
+    command_buffer::{AutoCommandBufferBuilder, ClearColorImageInfo, PrimaryAutoCommandBuffer},
+    descriptor_set::{DescriptorSet, WriteDescriptorSet},
+    format::{ClearColorValue, Format},
+    image::{ImageUsage, view::ImageView},
+    pipeline::{Pipeline, PipelineBindPoint},
+};
+
+use crate::{
+    error::RawProcessorError,
+    pipeline::{
+        context,
+        shaders::Shader,
+        stage::{self, StageInPipeline, StageOutput, StageResources},
+    },
+};
+
+// Levels of the alignment pyramid, each one decimated by 4 from the previous one
+const LEVELS: usize = 4;
+
+// Tiles are 16x16 samples and overlap by half, the same grid is used by the merge
+const TILE_STRIDE: u32 = 8;
+
+// Tiles covering an extent, starting half a tile before it
+pub fn tile_count(extent: [u32; 3]) -> [u32; 3] {
+    [
+        extent[0].div_ceil(TILE_STRIDE) + 1,
+        extent[1].div_ceil(TILE_STRIDE) + 1,
+        1,
+    ]
+}
+
+// Averages the quads of the shifted mosaic into a half resolution gray image
+struct Grayscale {
+    extent: [u32; 3],
+}
+
+// Builds the next coarser level of the pyramid
+struct Downsample {
+    extent: [u32; 3],
+}
+
+// Searches the offset of every tile of a level around the offset of the coarser level
+struct AlignLevel {
+    search_radius: i32,
+
+    tiles: [u32; 3],
+}
+
+impl StageInPipeline for Grayscale {
+    fn create_stage_resources(
+        &self,
+        context: &context::Context,
+        input: Option<StageOutput>,
+    ) -> Result<StageResources, RawProcessorError> {
+        let (_, gray_image_view) = {
+            let image = context
+                .pool
+                .image(Format::R32_SFLOAT, self.extent, ImageUsage::STORAGE)?;
+
+            let view = ImageView::new_default(image.clone())?;
+
+            (image, view)
+        };
+
+        let compute_pipeline = context.pipelines.get(Shader::Grayscale);
+
+        let layout = compute_pipeline.layout().set_layouts().get(0).unwrap();
+        let descriptor_set = DescriptorSet::new(
+            context.descriptor_set_allocator.clone(),
+            layout.clone(),
+            [
+                WriteDescriptorSet::image_view(
+                    0,
+                    input.unwrap().image_views.get(0).unwrap().clone(),
+                ),
+                WriteDescriptorSet::image_view(1, gray_image_view.clone()),
+            ],
+            [],
+        )?;
+
+        Ok(StageResources {
+            compute_pipeline,
+            descriptor_set,
+            image_views: vec![gray_image_view],
+            buffers: vec![],
+        })
+    }
+
+    fn bind_stage_pipeline_and_dispatch(
+        &self,
+        command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        resources: &StageResources,
+        work_groups: [u32; 3],
+    ) -> Result<(), RawProcessorError> {
+        command_buffer_builder
+            .bind_pipeline_compute(resources.compute_pipeline.clone())?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                resources.compute_pipeline.layout().clone(),
+                0,
+                resources.descriptor_set.clone(),
+            )?;
+
+        unsafe {
+            command_buffer_builder.dispatch(work_groups)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl StageInPipeline for Downsample {
+    fn create_stage_resources(
+        &self,
+        context: &context::Context,
+        input: Option<StageOutput>,
+    ) -> Result<StageResources, RawProcessorError> {
+        let (_, level_image_view) = {
+            let image = context
+                .pool
+                .image(Format::R32_SFLOAT, self.extent, ImageUsage::STORAGE)?;
+
+            let view = ImageView::new_default(image.clone())?;
+
+            (image, view)
+        };
+
+        let compute_pipeline = context.pipelines.get(Shader::Downsample);
+
+        let layout = compute_pipeline.layout().set_layouts().get(0).unwrap();
+        let descriptor_set = DescriptorSet::new(
+            context.descriptor_set_allocator.clone(),
+            layout.clone(),
+            [
+                WriteDescriptorSet::image_view(
+                    0,
+                    input.unwrap().image_views.get(0).unwrap().clone(),
+                ),
+                WriteDescriptorSet::image_view(1, level_image_view.clone()),
+            ],
+            [],
+        )?;
+
+        Ok(StageResources {
+            compute_pipeline,
+            descriptor_set,
+            image_views: vec![level_image_view],
+            buffers: vec![],
+        })
+    }
+
+    fn bind_stage_pipeline_and_dispatch(
+        &self,
+        command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        resources: &StageResources,
+        work_groups: [u32; 3],
+    ) -> Result<(), RawProcessorError> {
+        command_buffer_builder
+            .bind_pipeline_compute(resources.compute_pipeline.clone())?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                resources.compute_pipeline.layout().clone(),
+                0,
+                resources.descriptor_set.clone(),
+            )?;
+
+        unsafe {
+            command_buffer_builder.dispatch(work_groups)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl StageInPipeline for AlignLevel {
+    // Input holds the reference level, the alternate level and the coarser alignment
+    fn create_stage_resources(
+        &self,
+        context: &context::Context,
+        input: Option<StageOutput>,
+    ) -> Result<StageResources, RawProcessorError> {
+        let input = input.unwrap();
+
+        let (_, alignment_image_view) = {
+            let image = context
+                .pool
+                .image(Format::R32G32_SINT, self.tiles, ImageUsage::STORAGE)?;
+
+            let view = ImageView::new_default(image.clone())?;
+
+            (image, view)
+        };
+
+        let compute_pipeline = context.pipelines.get(Shader::Align);
+
+        let layout = compute_pipeline.layout().set_layouts().get(0).unwrap();
+        let descriptor_set = DescriptorSet::new(
+            context.descriptor_set_allocator.clone(),
+            layout.clone(),
+            [
+                WriteDescriptorSet::image_view(0, input.image_views[0].clone()),
+                WriteDescriptorSet::image_view(1, input.image_views[1].clone()),
+                WriteDescriptorSet::image_view(2, input.image_views[2].clone()),
+                WriteDescriptorSet::image_view(3, alignment_image_view.clone()),
+            ],
+            [],
+        )?;
+
+        Ok(StageResources {
+            compute_pipeline,
+            descriptor_set,
+            image_views: vec![alignment_image_view],
+            buffers: vec![],
+        })
+    }
+
+    fn bind_stage_pipeline_and_dispatch(
+        &self,
+        command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        resources: &StageResources,
+        work_groups: [u32; 3],
+    ) -> Result<(), RawProcessorError> {
+        #[derive(BufferContents)]
+        #[repr(C)]
+        struct Constants {
+            search_radius: i32,
+        }
+
+        let constants = Constants {
+            search_radius: self.search_radius,
+        };
+
+        command_buffer_builder
+            .bind_pipeline_compute(resources.compute_pipeline.clone())?
+            .push_constants(resources.compute_pipeline.layout().clone(), 0, constants)?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                resources.compute_pipeline.layout().clone(),
+                0,
+                resources.descriptor_set.clone(),
+            )?;
+
+        unsafe {
+            command_buffer_builder.dispatch(work_groups)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Gray pyramid of a shifted mosaic, finest level first
+pub fn build_pyramid(
+    context: &context::Context,
+    command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    raw_shifted: Arc<ImageView>,
+    extent: [u32; 3],
+) -> Result<Vec<Arc<ImageView>>, RawProcessorError> {
+    let mut extent = [extent[0] / 2, extent[1] / 2, 1];
+
+    let mut output = stage::record_stage(
+        &Grayscale { extent },
+        context,
+        command_buffer_builder,
+        Some(StageOutput {
+            image_views: vec![raw_shifted],
+            buffers: vec![],
+        }),
+        stage::work_groups(extent),
+    )?;
+
+    let mut levels = vec![output.image_views[0].clone()];
+
+    for _ in 1..LEVELS {
+        extent = [extent[0].div_ceil(4), extent[1].div_ceil(4), 1];
+
+        output = stage::record_stage(
+            &Downsample { extent },
+            context,
+            command_buffer_builder,
+            Some(output),
+            stage::work_groups(extent),
+        )?;
+
+        levels.push(output.image_views[0].clone());
+    }
+
+    Ok(levels)
+}
+
+// Offset of every tile of the alternate frame, in samples of the finest level, that is in
+// quads of the mosaic
+pub fn align(
+    context: &context::Context,
+    command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    reference: &[Arc<ImageView>],
+    alternate: &[Arc<ImageView>],
+) -> Result<Arc<ImageView>, RawProcessorError> {
+    // The search at the coarsest level starts from a zero offset
+    let mut alignment = {
+        let image = context.pool.image(
+            Format::R32G32_SINT,
+            [1, 1, 1],
+            ImageUsage::STORAGE | ImageUsage::TRANSFER_DST,
+        )?;
+
+        command_buffer_builder.clear_color_image(ClearColorImageInfo {
+            clear_value: ClearColorValue::Int([0; 4]),
+            ..ClearColorImageInfo::image(image.clone())
+        })?;
+
+        ImageView::new_default(image)?
+    };
+
+    for level in (0..LEVELS).rev() {
+        let tiles = tile_count(reference[level].image().extent());
+
+        // Offsets inherited from the coarser level are off by up to half its decimation
+        let stage = AlignLevel {
+            search_radius: if level == 0 { 2 } else { 4 },
+            tiles,
+        };
+
+        let output = stage::record_stage(
+            &stage,
+            context,
+            command_buffer_builder,
+            Some(StageOutput {
+                image_views: vec![
+                    reference[level].clone(),
+                    alternate[level].clone(),
+                    alignment,
+                ],
+                buffers: vec![],
+            }),
+            stage::work_groups(tiles),
+        )?;
+
+        alignment = output.image_views[0].clone();
+    }
+
+    Ok(alignment)
+}
@@ -0,0 +1,356 @@
+use std::sync::Arc;
+
+use vulkano::{
+    DeviceSize,
+    buffer::{BufferContents, BufferUsage, Subbuffer},
+    command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer},
+    descriptor_set::{DescriptorSet, WriteDescriptorSet},
+    image::view::ImageView,
+    pipeline::{Pipeline, PipelineBindPoint},
+};
+
+use crate::{
+    error::RawProcessorError,
+    pipeline::{
+        context,
+        shaders::Shader,
+        stage::{self, StageInPipeline, StageOutput, StageResources},
+    },
+    processor::WhiteBalance,
+};
+
+// Floats written by every work group, see awbstatistics.slang
+const RECORD_LEN: usize = 12;
+
+// Gains of each RGGB channel followed by their clip levels, see awbgains.slang
+const WHITE_BALANCE_LEN: usize = 8;
+
+// Fraction of the white level from which samples count as clipped, as kClipMargin of
+// awbstatistics.slang
+const CLIP_MARGIN: f32 = 0.98;
+
+// Fraction of the gain of each channel at which its normalized samples count as clipped.
+// Merged and defect corrected samples average clipped ones with their neighbors and rarely
+// reach the white level itself.
+pub fn clip_factor(black_level: [f32; 4], white_level: f32) -> [f32; 4] {
+    black_level
+        .map(|black_level| (white_level * CLIP_MARGIN - black_level) / (white_level - black_level))
+}
+
+// Reduces the quads covered by each work group into a record of the partials buffer
+struct AwbStatistics {
+    black_level: [f32; 4],
+    white_level: f32,
+}
+
+impl StageInPipeline for AwbStatistics {
+    fn create_stage_resources(
+        &self,
+        context: &context::Context,
+        input: Option<StageOutput>,
+    ) -> Result<StageResources, RawProcessorError> {
+        let raw_shifted = input.unwrap().image_views.get(0).unwrap().clone();
+
+        let partials_buffer = {
+            let [width, height, _] = raw_shifted.image().extent();
+            let [groups_x, groups_y, _] = stage::work_groups([width / 2, height / 2, 1]);
+
+            context.pool.buffer(
+                (groups_x as usize * groups_y as usize * RECORD_LEN * 4) as DeviceSize,
+                BufferUsage::STORAGE_BUFFER,
+            )?
+        };
+
+        let compute_pipeline = context.pipelines.get(Shader::AwbStatistics);
+
+        let layout = compute_pipeline.layout().set_layouts().get(0).unwrap();
+        let descriptor_set = DescriptorSet::new(
+            context.descriptor_set_allocator.clone(),
+            layout.clone(),
+            [
+                WriteDescriptorSet::image_view(0, raw_shifted.clone()),
+                WriteDescriptorSet::buffer(1, partials_buffer.clone()),
+            ],
+            [],
+        )?;
+
+        Ok(StageResources {
+            compute_pipeline,
+            descriptor_set,
+            image_views: vec![raw_shifted],
+            buffers: vec![partials_buffer],
+        })
+    }
+
+    fn bind_stage_pipeline_and_dispatch(
+        &self,
+        command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        resources: &StageResources,
+        work_groups: [u32; 3],
+    ) -> Result<(), RawProcessorError> {
+        #[derive(BufferContents)]
+        #[repr(C)]
+        struct Constants {
+            black_level: [f32; 4],
+            white_level: f32,
+        }
+
+        let constants = Constants {
+            black_level: self.black_level,
+            white_level: self.white_level,
+        };
+
+        command_buffer_builder
+            .bind_pipeline_compute(resources.compute_pipeline.clone())?
+            .push_constants(resources.compute_pipeline.layout().clone(), 0, constants)?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                resources.compute_pipeline.layout().clone(),
+                0,
+                resources.descriptor_set.clone(),
+            )?;
+
+        unsafe {
+            command_buffer_builder.dispatch(work_groups)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Reduces the partials of every work group into the single record read by AwbGains
+struct AwbReduce {
+    records: u32,
+}
+
+impl StageInPipeline for AwbReduce {
+    fn create_stage_resources(
+        &self,
+        context: &context::Context,
+        input: Option<StageOutput>,
+    ) -> Result<StageResources, RawProcessorError> {
+        let partials_buffer = input.unwrap().buffers.get(0).unwrap().clone();
+
+        let statistics_buffer = context
+            .pool
+            .buffer((RECORD_LEN * 4) as DeviceSize, BufferUsage::STORAGE_BUFFER)?;
+
+        let compute_pipeline = context.pipelines.get(Shader::AwbReduce);
+
+        let layout = compute_pipeline.layout().set_layouts().get(0).unwrap();
+        let descriptor_set = DescriptorSet::new(
+            context.descriptor_set_allocator.clone(),
+            layout.clone(),
+            [
+                WriteDescriptorSet::buffer(0, partials_buffer),
+                WriteDescriptorSet::buffer(1, statistics_buffer.clone()),
+            ],
+            [],
+        )?;
+
+        Ok(StageResources {
+            compute_pipeline,
+            descriptor_set,
+            image_views: vec![],
+            buffers: vec![statistics_buffer],
+        })
+    }
+
+    fn bind_stage_pipeline_and_dispatch(
+        &self,
+        command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        resources: &StageResources,
+        work_groups: [u32; 3],
+    ) -> Result<(), RawProcessorError> {
+        #[derive(BufferContents)]
+        #[repr(C)]
+        struct Constants {
+            records: u32,
+        }
+
+        let constants = Constants {
+            records: self.records,
+        };
+
+        command_buffer_builder
+            .bind_pipeline_compute(resources.compute_pipeline.clone())?
+            .push_constants(resources.compute_pipeline.layout().clone(), 0, constants)?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                resources.compute_pipeline.layout().clone(),
+                0,
+                resources.descriptor_set.clone(),
+            )?;
+
+        unsafe {
+            command_buffer_builder.dispatch(work_groups)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Picks the illuminant of the estimation mode out of the statistics and writes the gains and
+// clip levels, or the fallback gains when the frame has no usable statistics
+struct AwbGains {
+    white_balance: WhiteBalance,
+    fallback_gains: [f32; 4],
+    clip_factor: [f32; 4],
+}
+
+impl StageInPipeline for AwbGains {
+    fn create_stage_resources(
+        &self,
+        context: &context::Context,
+        input: Option<StageOutput>,
+    ) -> Result<StageResources, RawProcessorError> {
+        let statistics_buffer = input.unwrap().buffers.get(0).unwrap().clone();
+
+        let white_balance_buffer = context.pool.buffer(
+            (WHITE_BALANCE_LEN * 4) as DeviceSize,
+            BufferUsage::STORAGE_BUFFER,
+        )?;
+
+        let compute_pipeline = context.pipelines.get(Shader::AwbGains);
+
+        let layout = compute_pipeline.layout().set_layouts().get(0).unwrap();
+        let descriptor_set = DescriptorSet::new(
+            context.descriptor_set_allocator.clone(),
+            layout.clone(),
+            [
+                WriteDescriptorSet::buffer(0, statistics_buffer),
+                WriteDescriptorSet::buffer(1, white_balance_buffer.clone()),
+            ],
+            [],
+        )?;
+
+        Ok(StageResources {
+            compute_pipeline,
+            descriptor_set,
+            image_views: vec![],
+            buffers: vec![white_balance_buffer],
+        })
+    }
+
+    fn bind_stage_pipeline_and_dispatch(
+        &self,
+        command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        resources: &StageResources,
+        work_groups: [u32; 3],
+    ) -> Result<(), RawProcessorError> {
+        #[derive(BufferContents)]
+        #[repr(C)]
+        struct Constants {
+            fallback_gains: [f32; 4],
+            clip_factor: [f32; 4],
+            mode: u32,
+        }
+
+        let constants = Constants {
+            fallback_gains: self.fallback_gains,
+            clip_factor: self.clip_factor,
+            mode: match self.white_balance {
+                WhiteBalance::WhitePatch => 1,
+                WhiteBalance::GrayEdge => 2,
+                _ => 0,
+            },
+        };
+
+        command_buffer_builder
+            .bind_pipeline_compute(resources.compute_pipeline.clone())?
+            .push_constants(resources.compute_pipeline.layout().clone(), 0, constants)?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                resources.compute_pipeline.layout().clone(),
+                0,
+                resources.descriptor_set.clone(),
+            )?;
+
+        unsafe {
+            command_buffer_builder.dispatch(work_groups)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Records the estimation of the white balance of a shifted mosaic, black level given per
+// channel of the shifted mosaic. The returned buffer holds the gains and clip levels of each
+// channel once the dispatches have executed, it stays on the GPU so that the rest of the
+// pipeline is recorded into the same command buffer.
+pub fn record_white_balance(
+    context: &context::Context,
+    command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    raw_shifted: Arc<ImageView>,
+    black_level: [f32; 4],
+    white_level: f32,
+    white_balance: WhiteBalance,
+    fallback_gains: [f32; 4],
+) -> Result<Subbuffer<[u8]>, RawProcessorError> {
+    let [width, height, _] = raw_shifted.image().extent();
+    let work_groups = stage::work_groups([width / 2, height / 2, 1]);
+
+    let partials = stage::record_stage(
+        &AwbStatistics {
+            black_level,
+            white_level,
+        },
+        context,
+        command_buffer_builder,
+        Some(StageOutput {
+            image_views: vec![raw_shifted],
+            buffers: vec![],
+        }),
+        work_groups,
+    )?;
+
+    let statistics = stage::record_stage(
+        &AwbReduce {
+            records: work_groups[0] * work_groups[1],
+        },
+        context,
+        command_buffer_builder,
+        Some(StageOutput {
+            image_views: vec![],
+            buffers: partials.buffers,
+        }),
+        [1, 1, 1],
+    )?;
+
+    let output = stage::record_stage(
+        &AwbGains {
+            white_balance,
+            fallback_gains,
+            clip_factor: clip_factor(black_level, white_level),
+        },
+        context,
+        command_buffer_builder,
+        Some(statistics),
+        [1, 1, 1],
+    )?;
+
+    Ok(output.buffers[0].clone())
+}
+
+// White balance known on the host, laid out as the one estimated by record_white_balance
+pub fn white_balance_buffer(
+    context: &context::Context,
+    color_gains: [f32; 4],
+    clip_level: [f32; 4],
+) -> Result<Subbuffer<[u8]>, RawProcessorError> {
+    let buffer = context.pool.buffer(
+        (WHITE_BALANCE_LEN * 4) as DeviceSize,
+        BufferUsage::STORAGE_BUFFER,
+    )?;
+
+    let mut data = buffer.write()?;
+    for (bytes, value) in data
+        .chunks_exact_mut(4)
+        .zip(color_gains.iter().chain(&clip_level))
+    {
+        bytes.copy_from_slice(&value.to_ne_bytes());
+    }
+    drop(data);
+
+    Ok(buffer)
+}
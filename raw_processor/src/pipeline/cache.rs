@@ -0,0 +1,261 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use log::{info, warn};
+use vulkano::{
+    device::Device,
+    pipeline::cache::{PipelineCache, PipelineCacheCreateInfo},
+};
+
+use crate::error::RawProcessorError;
+
+const MAGIC: [u8; 4] = *b"RPPC";
+const HEADER_VERSION: u32 = 1;
+const HEADER_LEN: usize = 4 + 4 + 4 + 4 + 4 + 16 + 16 + 8 + 8;
+
+// Identifies the device and driver that produced a cache blob. The driver also checks its
+// own header, but it trusts the payload blindly, so we refuse anything that does not match
+// exactly or that has been truncated or corrupted on disk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct CacheKey {
+    vendor_id: u32,
+    device_id: u32,
+    driver_version: u32,
+    pipeline_cache_uuid: [u8; 16],
+    device_uuid: [u8; 16],
+}
+
+impl CacheKey {
+    fn new(device: &Device) -> CacheKey {
+        let properties = device.physical_device().properties();
+
+        CacheKey {
+            vendor_id: properties.vendor_id,
+            device_id: properties.device_id,
+            driver_version: properties.driver_version,
+            pipeline_cache_uuid: properties.pipeline_cache_uuid,
+            device_uuid: properties.device_uuid.unwrap_or_default(),
+        }
+    }
+}
+
+// FNV-1a, only used to detect corrupted files
+fn checksum(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn encode(key: &CacheKey, data: &[u8]) -> Vec<u8> {
+    let mut blob = Vec::with_capacity(HEADER_LEN + data.len());
+
+    blob.extend_from_slice(&MAGIC);
+    blob.extend_from_slice(&HEADER_VERSION.to_le_bytes());
+    blob.extend_from_slice(&key.vendor_id.to_le_bytes());
+    blob.extend_from_slice(&key.device_id.to_le_bytes());
+    blob.extend_from_slice(&key.driver_version.to_le_bytes());
+    blob.extend_from_slice(&key.pipeline_cache_uuid);
+    blob.extend_from_slice(&key.device_uuid);
+    blob.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    blob.extend_from_slice(&checksum(data).to_le_bytes());
+    blob.extend_from_slice(data);
+
+    blob
+}
+
+fn decode(key: &CacheKey, blob: &[u8]) -> Option<Vec<u8>> {
+    if blob.len() < HEADER_LEN {
+        return None;
+    }
+
+    let (header, data) = blob.split_at(HEADER_LEN);
+
+    let u32_at = |offset: usize| u32::from_le_bytes(header[offset..][..4].try_into().unwrap());
+    let u64_at = |offset: usize| u64::from_le_bytes(header[offset..][..8].try_into().unwrap());
+
+    let stored_key = CacheKey {
+        vendor_id: u32_at(8),
+        device_id: u32_at(12),
+        driver_version: u32_at(16),
+        pipeline_cache_uuid: header[20..36].try_into().unwrap(),
+        device_uuid: header[36..52].try_into().unwrap(),
+    };
+
+    let valid = header[0..4] == MAGIC
+        && u32_at(4) == HEADER_VERSION
+        && stored_key == *key
+        && u64_at(52) == data.len() as u64
+        && u64_at(60) == checksum(data);
+
+    valid.then(|| data.to_vec())
+}
+
+// Vulkan pipeline cache backed by a file, so that shaders compiled in a previous run of the
+// application do not have to be compiled again by the driver
+pub struct PersistentPipelineCache {
+    path: PathBuf,
+    key: CacheKey,
+    cache: Arc<PipelineCache>,
+}
+
+impl PersistentPipelineCache {
+    pub fn open(
+        device: Arc<Device>,
+        path: &Path,
+    ) -> Result<PersistentPipelineCache, RawProcessorError> {
+        let key = CacheKey::new(&device);
+
+        let initial_data = match fs::read(path) {
+            Ok(blob) => match decode(&key, &blob) {
+                Some(data) => {
+                    info!(
+                        "Loaded {} bytes of pipeline cache from {path:?}",
+                        data.len()
+                    );
+                    data
+                }
+                None => {
+                    warn!(
+                        "Discarding pipeline cache {path:?} created for another device or driver"
+                    );
+                    Vec::new()
+                }
+            },
+            Err(err) => {
+                info!("No pipeline cache loaded from {path:?}: {err}");
+                Vec::new()
+            }
+        };
+
+        // Safety: the data was produced by `PipelineCache::get_data` on this very device and
+        // driver, as checked by `decode`
+        let cache = unsafe {
+            PipelineCache::new(
+                device,
+                PipelineCacheCreateInfo {
+                    initial_data,
+                    ..Default::default()
+                },
+            )
+        }?;
+
+        Ok(PersistentPipelineCache {
+            path: path.to_path_buf(),
+            key,
+            cache,
+        })
+    }
+
+    pub fn cache(&self) -> Arc<PipelineCache> {
+        self.cache.clone()
+    }
+
+    pub fn save(&self) -> Result<(), RawProcessorError> {
+        let data = self.cache.get_data()?;
+
+        // Write to a temporary file first so that a crash never leaves a partial cache behind
+        let temporary_path = self.path.with_extension("tmp");
+        fs::write(&temporary_path, encode(&self.key, &data))?;
+        fs::rename(&temporary_path, &self.path)?;
+
+        info!(
+            "Saved {} bytes of pipeline cache to {:?}",
+            data.len(),
+            self.path
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: CacheKey = CacheKey {
+        vendor_id: 0x13b5,
+        device_id: 0x92020010,
+        driver_version: 0x8000000,
+        pipeline_cache_uuid: [1; 16],
+        device_uuid: [2; 16],
+    };
+
+    const DATA: &[u8] = b"pipeline cache payload";
+
+    #[test]
+    fn decodes_encoded_blob() {
+        let blob = encode(&KEY, DATA);
+
+        assert_eq!(blob.len(), HEADER_LEN + DATA.len());
+        assert_eq!(decode(&KEY, &blob).as_deref(), Some(DATA));
+    }
+
+    #[test]
+    fn decodes_empty_cache() {
+        assert_eq!(decode(&KEY, &encode(&KEY, &[])), Some(vec![]));
+    }
+
+    #[test]
+    fn rejects_other_device_or_driver() {
+        let blob = encode(&KEY, DATA);
+
+        let keys = [
+            CacheKey {
+                device_id: 0x92020011,
+                ..KEY
+            },
+            CacheKey {
+                driver_version: 0x8000001,
+                ..KEY
+            },
+            CacheKey {
+                pipeline_cache_uuid: [3; 16],
+                ..KEY
+            },
+            CacheKey {
+                device_uuid: [0; 16],
+                ..KEY
+            },
+        ];
+
+        for key in keys {
+            assert_eq!(decode(&key, &blob), None);
+        }
+    }
+
+    #[test]
+    fn rejects_corrupted_blob() {
+        let blob = encode(&KEY, DATA);
+
+        // Flipped payload byte, caught by the checksum
+        let mut corrupted = blob.clone();
+        corrupted[HEADER_LEN + 3] ^= 0x40;
+        assert_eq!(decode(&KEY, &corrupted), None);
+
+        // Flipped checksum byte
+        let mut corrupted = blob.clone();
+        corrupted[HEADER_LEN - 1] ^= 0x01;
+        assert_eq!(decode(&KEY, &corrupted), None);
+
+        // Other magic or header version
+        let mut corrupted = blob.clone();
+        corrupted[0] = b'X';
+        assert_eq!(decode(&KEY, &corrupted), None);
+
+        let mut corrupted = blob.clone();
+        corrupted[4] = 2;
+        assert_eq!(decode(&KEY, &corrupted), None);
+    }
+
+    #[test]
+    fn rejects_truncated_blob() {
+        let blob = encode(&KEY, DATA);
+
+        assert_eq!(decode(&KEY, &blob[..blob.len() - 1]), None);
+        assert_eq!(decode(&KEY, &blob[..HEADER_LEN - 1]), None);
+        assert_eq!(decode(&KEY, &[]), None);
+    }
+}
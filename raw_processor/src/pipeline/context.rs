@@ -1,55 +1,67 @@
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc};
 
+use log::info;
 use vulkano::{
     VulkanLibrary,
     command_buffer::allocator::{
         StandardCommandBufferAllocator, StandardCommandBufferAllocatorCreateInfo,
     },
     descriptor_set::allocator::StandardDescriptorSetAllocator,
-    device::{Device, DeviceCreateInfo, DeviceFeatures, Queue, QueueCreateInfo, QueueFlags},
+    device::{Device, DeviceCreateInfo, Queue, QueueCreateInfo},
     instance::{Instance, InstanceCreateFlags, InstanceCreateInfo},
     memory::allocator::StandardMemoryAllocator,
 };
 
+use crate::{
+    error::RawProcessorError,
+    pipeline::{
+        cache::PersistentPipelineCache,
+        device::{self, DeviceCapabilities, DeviceSelection, SelectedDevice},
+        pool::ResourcePool,
+        registry::PipelineRegistry,
+    },
+};
+
+#[derive(Clone, Debug, Default)]
+pub struct ContextCreateInfo {
+    pub device_selection: DeviceSelection,
+    // File the Vulkan pipeline cache is loaded from and saved to, if any
+    pub pipeline_cache_path: Option<PathBuf>,
+}
+
 pub struct Context {
     pub device: Arc<Device>,
     pub queue: Arc<Queue>,
-    pub memory_allocator: Arc<StandardMemoryAllocator>,
     pub descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
     pub command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    pub pipelines: PipelineRegistry,
+    pub pipeline_cache: Option<PersistentPipelineCache>,
+    pub pool: ResourcePool,
+
+    pub capabilities: DeviceCapabilities,
 }
 
 impl Context {
-    pub fn new(library: Arc<VulkanLibrary>) -> Box<Context> {
+    pub fn new(
+        library: Arc<VulkanLibrary>,
+        create_info: ContextCreateInfo,
+    ) -> Result<Box<Context>, RawProcessorError> {
         let instance = Instance::new(
             library,
             InstanceCreateInfo {
                 flags: InstanceCreateFlags::ENUMERATE_PORTABILITY,
                 ..Default::default()
             },
-        )
-        .unwrap();
+        )?;
 
-        // TODO: Find actual suitable device
-        let physical_device = instance
-            .enumerate_physical_devices()
-            .expect("Failed to enumerate physical devices")
-            .next()
-            .expect("Failed to find physical device");
-
-        let queue_family_index = physical_device
-            .queue_family_properties()
-            .iter()
-            .enumerate()
-            .position(|(_, queue_family_properties)| {
-                queue_family_properties
-                    .queue_flags
-                    .contains(QueueFlags::COMPUTE)
-            })
-            .expect("Failed to find a graphical queue family")
-            as u32;
+        let SelectedDevice {
+            physical_device,
+            queue_family_index,
+            enabled_features,
+            capabilities,
+        } = device::select_physical_device(&instance, &create_info.device_selection)?;
 
-        // info!("Queue family with compute {:?}", queue_family_index);
+        info!("Selected device {capabilities}");
 
         let (device, mut queues) = Device::new(
             physical_device,
@@ -58,18 +70,11 @@ impl Context {
                     queue_family_index,
                     ..Default::default()
                 }],
-                enabled_features: DeviceFeatures {
-                    // Panic if shader_int16 and shader_float16 are not supported by device
-                    shader_int16: true,
-                    shader_float16: true,
-                    ..Default::default()
-                },
+                enabled_features,
                 ..Default::default()
             },
-        )
-        .expect("Failed to create device");
-
-        let queue = queues.next().unwrap();
+        )?;
+        let queue = queues.next().ok_or(RawProcessorError::NoSuitableDevice)?;
 
         let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
         let descriptor_set_allocator = Arc::new(StandardDescriptorSetAllocator::new(
@@ -81,13 +86,35 @@ impl Context {
             StandardCommandBufferAllocatorCreateInfo::default(),
         ));
 
-        // Sorry, I still don't know how to return Result<>
-        Box::new(Context {
+        let pipeline_cache = create_info
+            .pipeline_cache_path
+            .as_deref()
+            .map(|path| PersistentPipelineCache::open(device.clone(), path))
+            .transpose()?;
+
+        let pipelines = PipelineRegistry::new(
+            device.clone(),
+            pipeline_cache.as_ref().map(PersistentPipelineCache::cache),
+        )?;
+
+        let pool = ResourcePool::new(memory_allocator);
+
+        Ok(Box::new(Context {
             device,
             queue,
-            memory_allocator,
             descriptor_set_allocator,
             command_buffer_allocator,
-        })
+            pipelines,
+            pipeline_cache,
+            pool,
+            capabilities,
+        }))
+    }
+
+    pub fn save_pipeline_cache(&self) -> Result<(), RawProcessorError> {
+        match &self.pipeline_cache {
+            Some(pipeline_cache) => pipeline_cache.save(),
+            None => Ok(()),
+        }
     }
 }
@@ -0,0 +1,276 @@
+use std::{fmt, sync::Arc};
+
+use log::info;
+use vulkano::{
+    Version,
+    device::{
+        DeviceFeatures, QueueFlags,
+        physical::{PhysicalDevice, PhysicalDeviceType},
+    },
+    instance::Instance,
+};
+
+use crate::error::RawProcessorError;
+
+// Features every pipeline shader depends on
+pub const REQUIRED_FEATURES: DeviceFeatures = DeviceFeatures {
+    shader_int16: true,
+    shader_float16: true,
+    ..DeviceFeatures::empty()
+};
+
+// Features that are enabled when available but not needed to run the pipeline
+pub const OPTIONAL_FEATURES: DeviceFeatures = DeviceFeatures {
+    shader_storage_image_read_without_format: true,
+    shader_storage_image_write_without_format: true,
+    shader_storage_image_extended_formats: true,
+    ..DeviceFeatures::empty()
+};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum DeviceSelection {
+    // Highest scoring device among all suitable ones
+    #[default]
+    Auto,
+    // Device at the given position in the instance enumeration order
+    Index(usize),
+    // Best device whose name contains the given string, case insensitive
+    Name(String),
+    // CPU implementation such as lavapipe or SwiftShader
+    Software,
+}
+
+// Report of the device the context runs on
+#[derive(Clone, Debug)]
+pub struct DeviceCapabilities {
+    pub name: String,
+    pub device_type: PhysicalDeviceType,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub api_version: Version,
+    pub driver_version: u32,
+    pub driver_name: Option<String>,
+
+    pub queue_family_index: u32,
+    // Queue family without graphics support, usually mapped to separate hardware
+    pub dedicated_compute_queue: bool,
+
+    pub enabled_features: DeviceFeatures,
+
+    pub max_compute_work_group_count: [u32; 3],
+    pub max_compute_work_group_size: [u32; 3],
+    pub max_compute_work_group_invocations: u32,
+    pub max_push_constants_size: u32,
+    pub max_storage_buffer_range: u32,
+    pub max_image_dimension_2d: u32,
+    pub subgroup_size: Option<u32>,
+}
+
+impl fmt::Display for DeviceCapabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} ({:?}, vendor {:#06x}, device {:#06x})",
+            self.name, self.device_type, self.vendor_id, self.device_id
+        )?;
+        writeln!(
+            f,
+            "Vulkan {}, driver {} version {:#x}",
+            self.api_version,
+            self.driver_name.as_deref().unwrap_or("unknown"),
+            self.driver_version
+        )?;
+        writeln!(
+            f,
+            "queue family {}{}",
+            self.queue_family_index,
+            if self.dedicated_compute_queue {
+                " (dedicated compute)"
+            } else {
+                ""
+            }
+        )?;
+        writeln!(
+            f,
+            "work group count {:?}, size {:?}, invocations {}",
+            self.max_compute_work_group_count,
+            self.max_compute_work_group_size,
+            self.max_compute_work_group_invocations
+        )?;
+        writeln!(
+            f,
+            "push constants {} bytes, storage buffer {} bytes, image 2D {} pixels, subgroup size {:?}",
+            self.max_push_constants_size,
+            self.max_storage_buffer_range,
+            self.max_image_dimension_2d,
+            self.subgroup_size
+        )?;
+        write!(f, "features {:?}", self.enabled_features)
+    }
+}
+
+struct Candidate {
+    physical_device: Arc<PhysicalDevice>,
+    queue_family_index: u32,
+    dedicated_compute_queue: bool,
+    score: u32,
+}
+
+fn device_type_score(device_type: PhysicalDeviceType) -> u32 {
+    match device_type {
+        PhysicalDeviceType::DiscreteGpu => 400,
+        PhysicalDeviceType::IntegratedGpu => 300,
+        PhysicalDeviceType::VirtualGpu => 200,
+        PhysicalDeviceType::Cpu => 100,
+        _ => 0,
+    }
+}
+
+// Prefers queue families that only do compute, falling back to any family with compute support
+fn find_compute_queue_family(physical_device: &PhysicalDevice) -> Option<(u32, bool)> {
+    let families = physical_device.queue_family_properties();
+
+    let dedicated = families.iter().position(|properties| {
+        properties.queue_flags.contains(QueueFlags::COMPUTE)
+            && !properties.queue_flags.intersects(QueueFlags::GRAPHICS)
+    });
+
+    match dedicated {
+        Some(index) => Some((index as u32, true)),
+        None => families
+            .iter()
+            .position(|properties| properties.queue_flags.contains(QueueFlags::COMPUTE))
+            .map(|index| (index as u32, false)),
+    }
+}
+
+fn evaluate(physical_device: Arc<PhysicalDevice>) -> Result<Candidate, RawProcessorError> {
+    let (queue_family_index, dedicated_compute_queue) =
+        find_compute_queue_family(&physical_device).ok_or(RawProcessorError::NoSuitableDevice)?;
+
+    let supported_features = physical_device.supported_features();
+
+    if !supported_features.contains(&REQUIRED_FEATURES) {
+        return Err(RawProcessorError::MissingFeatures(Box::new(
+            REQUIRED_FEATURES.difference(supported_features),
+        )));
+    }
+
+    let optional_features = supported_features.intersection(&OPTIONAL_FEATURES);
+
+    let score = device_type_score(physical_device.properties().device_type)
+        + if dedicated_compute_queue { 50 } else { 0 }
+        + 10 * optional_features
+            .into_iter()
+            .filter(|(_, enabled)| *enabled)
+            .count() as u32;
+
+    Ok(Candidate {
+        physical_device,
+        queue_family_index,
+        dedicated_compute_queue,
+        score,
+    })
+}
+
+pub struct SelectedDevice {
+    pub physical_device: Arc<PhysicalDevice>,
+    pub queue_family_index: u32,
+    pub enabled_features: DeviceFeatures,
+    pub capabilities: DeviceCapabilities,
+}
+
+pub fn select_physical_device(
+    instance: &Arc<Instance>,
+    selection: &DeviceSelection,
+) -> Result<SelectedDevice, RawProcessorError> {
+    let physical_devices: Vec<_> = instance.enumerate_physical_devices()?.collect();
+
+    let requested: Vec<_> = match selection {
+        DeviceSelection::Auto => physical_devices,
+        DeviceSelection::Index(index) => {
+            physical_devices.into_iter().skip(*index).take(1).collect()
+        }
+        DeviceSelection::Name(name) => {
+            let name = name.to_lowercase();
+            physical_devices
+                .into_iter()
+                .filter(|physical_device| {
+                    physical_device
+                        .properties()
+                        .device_name
+                        .to_lowercase()
+                        .contains(&name)
+                })
+                .collect()
+        }
+        DeviceSelection::Software => physical_devices
+            .into_iter()
+            .filter(|physical_device| {
+                physical_device.properties().device_type == PhysicalDeviceType::Cpu
+            })
+            .collect(),
+    };
+
+    let mut best: Option<Candidate> = None;
+    let mut last_error = RawProcessorError::NoSuitableDevice;
+
+    for physical_device in requested {
+        info!(
+            "Found physical device {} ({:?})",
+            physical_device.properties().device_name,
+            physical_device.properties().device_type
+        );
+
+        match evaluate(physical_device) {
+            Ok(candidate) => {
+                if best
+                    .as_ref()
+                    .is_none_or(|best| candidate.score > best.score)
+                {
+                    best = Some(candidate);
+                }
+            }
+            Err(err) => last_error = err,
+        }
+    }
+
+    // Report why the last device was rejected when none is usable
+    let candidate = best.ok_or(last_error)?;
+
+    let enabled_features = REQUIRED_FEATURES.union(
+        &candidate
+            .physical_device
+            .supported_features()
+            .intersection(&OPTIONAL_FEATURES),
+    );
+
+    let properties = candidate.physical_device.properties();
+
+    let capabilities = DeviceCapabilities {
+        name: properties.device_name.clone(),
+        device_type: properties.device_type,
+        vendor_id: properties.vendor_id,
+        device_id: properties.device_id,
+        api_version: properties.api_version,
+        driver_version: properties.driver_version,
+        driver_name: properties.driver_name.clone(),
+        queue_family_index: candidate.queue_family_index,
+        dedicated_compute_queue: candidate.dedicated_compute_queue,
+        enabled_features,
+        max_compute_work_group_count: properties.max_compute_work_group_count,
+        max_compute_work_group_size: properties.max_compute_work_group_size,
+        max_compute_work_group_invocations: properties.max_compute_work_group_invocations,
+        max_push_constants_size: properties.max_push_constants_size,
+        max_storage_buffer_range: properties.max_storage_buffer_range,
+        max_image_dimension_2d: properties.max_image_dimension2_d,
+        subgroup_size: properties.subgroup_size,
+    };
+
+    Ok(SelectedDevice {
+        physical_device: candidate.physical_device,
+        queue_family_index: candidate.queue_family_index,
+        enabled_features,
+        capabilities,
+    })
+}
@@ -1,41 +1,159 @@
-use std::slice;
+use std::array;
 
+use log::info;
 use vulkano::{
     DeviceSize,
-    buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer},
+    buffer::{BufferContents, BufferUsage, Subbuffer},
     command_buffer::{
-        AutoCommandBufferBuilder, CommandBufferUsage, CopyBufferToImageInfo, CopyImageToBufferInfo,
-        PrimaryAutoCommandBuffer, PrimaryCommandBufferAbstract,
+        AutoCommandBufferBuilder, CommandBufferExecFuture, CommandBufferUsage,
+        CopyBufferToImageInfo, PrimaryAutoCommandBuffer,
     },
     descriptor_set::{DescriptorSet, WriteDescriptorSet},
     format::Format,
-    image::{Image, ImageCreateInfo, ImageUsage, view::ImageView},
-    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter},
-    pipeline::{
-        ComputePipeline, Pipeline, PipelineBindPoint, PipelineLayout,
-        PipelineShaderStageCreateInfo, compute::ComputePipelineCreateInfo,
-        layout::PipelineDescriptorSetLayoutCreateInfo,
+    image::{ImageUsage, view::ImageView},
+    pipeline::{Pipeline, PipelineBindPoint},
+    sync::{
+        self, GpuFuture,
+        future::{FenceSignalFuture, NowFuture},
     },
-    sync::{self, GpuFuture},
 };
 
-use crate::pipeline::{
-    context,
-    stage::{StageInPipeline, StageOutput, StageResources},
+use crate::{
+    color,
+    error::RawProcessorError,
+    pipeline::{
+        awb, context, fusion,
+        merge::{self, NoiseModel},
+        shaders::Shader,
+        stage::{self, StageInPipeline, StageOutput, StageResources},
+        unpack,
+    },
+    processor::{
+        ColorSpace, DemosaicAlgorithm, HighlightMode, LensShadingMap, OutputFormat,
+        ProcessingParams, RawFormat, RawImage, ToneMapOperator, WhiteBalance,
+    },
 };
 
-struct Stage0 {
+// Offset of the first red sample of the sensor mosaic, Stage0 shifts the mosaic by it so that
+// the following stages only have to handle RGGB
+fn shift_vector(color_filter_arrangement: i32) -> [i32; 2] {
+    match color_filter_arrangement {
+        0 /* RGGB */ => [0, 0],
+        1 /* GRBG */ => [1, 0],
+        2 /* GBRG */ => [0, 1],
+        3 /* BGGR */ => [1, 1],
+        _ => [0, 0],
+    }
+}
+
+// Reorders values given per channel of the sensor mosaic, such as the black level pattern, into
+// the channels of the shifted RGGB mosaic
+fn shift_channels<T: Copy>(values: [T; 4], color_filter_arrangement: i32) -> [T; 4] {
+    let [shift_x, shift_y] = shift_vector(color_filter_arrangement);
+
+    [0, 1, 2, 3].map(|index| {
+        let x = (index % 2 + shift_x) % 2;
+        let y = (index / 2 + shift_y) % 2;
+        values[(y * 2 + x) as usize]
+    })
+}
+
+// One bit per sample of the shifted mosaic, in row major order, set for the known bad pixels
+fn defect_mask(bad_pixels: &[[u32; 2]], shift_vector: [i32; 2], extent: [u32; 3]) -> Vec<u32> {
+    let [width, height, _] = extent;
+    let mut mask = vec![0u32; (width as usize * height as usize).div_ceil(32)];
+
+    for &[x, y] in bad_pixels {
+        // Stage0 reads sensor sample (x, y) + shift_vector into (x, y)
+        let x = x as i64 - shift_vector[0] as i64;
+        let y = y as i64 - shift_vector[1] as i64;
+
+        if (0..width as i64).contains(&x) && (0..height as i64).contains(&y) {
+            let index = y as usize * width as usize + x as usize;
+            mask[index / 32] |= 1 << (index % 32);
+        }
+    }
+
+    mask
+}
+
+// Gains of the lens shading map in the channel order of the shifted mosaic, a single unit gain
+// when the capture has no map
+fn shading_gains(
+    context: &context::Context,
+    map: Option<&LensShadingMap>,
+    color_filter_arrangement: i32,
+) -> Result<(Subbuffer<[u8]>, [i32; 2]), RawProcessorError> {
+    let (gains, map_size) = match map {
+        Some(map) => (&map.gains[..], [map.columns as i32, map.rows as i32]),
+        None => (&[1.0; 4][..], [1, 1]),
+    };
+
+    let buffer = context
+        .pool
+        .buffer((gains.len() * 4) as DeviceSize, BufferUsage::STORAGE_BUFFER)?;
+
+    // Stage0 moves odd sensor rows to even rows for GBRG and BGGR, which swaps the green channels
+    // of the map
+    let swap_green = shift_vector(color_filter_arrangement)[1] == 1;
+
+    let mut data = buffer.write()?;
+    for (point, gains) in data.chunks_exact_mut(16).zip(gains.chunks_exact(4)) {
+        let gains = if swap_green {
+            [gains[0], gains[2], gains[1], gains[3]]
+        } else {
+            [gains[0], gains[1], gains[2], gains[3]]
+        };
+
+        for (bytes, gain) in point.chunks_exact_mut(4).zip(gains) {
+            bytes.copy_from_slice(&gain.to_ne_bytes());
+        }
+    }
+    drop(data);
+
+    Ok((buffer, map_size))
+}
+
+// Noise profile assumed when merging bursts from devices that do not report
+// SENSOR_NOISE_PROFILE, about that of a phone sensor at a high sensitivity
+const DEFAULT_NOISE_PROFILE: [f32; 2] = [5.0e-4, 1.0e-6];
+
+// Unpacks MIPI packed RAW10 and RAW12 buffers ahead of Stage0
+struct Unpack<'a> {
+    format: RawFormat,
+
+    // Packed raw image buffer
+    buffer: &'a [u8],
+    row_stride: u32,
+
+    extent: [u32; 3],
+}
+
+struct Stage0<'a> {
     color_filter_arrangement: i32,
 
-    // Bayer raw image buffer
-    buffer: *const u8,
-    buffer_len: usize,
+    // Bayer raw image buffer, only uploaded when no Unpack stage runs before
+    format: RawFormat,
+    buffer: &'a [u8],
+    row_stride: u32,
+
+    extent: [u32; 3],
+}
+
+// Replaces hot, dead and listed defective samples of each frame between Stage0 and the merge
+struct DefectCorrection<'a> {
+    // In digital numbers
+    threshold: f32,
+    // In sensor coordinates
+    bad_pixels: &'a [[u32; 2]],
+    shift_vector: [i32; 2],
 
     extent: [u32; 3],
 }
 
 struct Stage1 {
-    color_gains: [f32; 4],
+    // Gains and clip levels of each channel, estimated on the GPU or written by the host
+    white_balance: Subbuffer<[u8]>,
 
     black_level: [i32; 4],
     white_level: i32,
@@ -43,151 +161,619 @@ struct Stage1 {
     extent: [u32; 3],
 }
 
+// Reconstructs clipped highlights in the normalized mosaic, after lens shading correction
+struct HighlightReconstruction {
+    mode: HighlightMode,
+    // Same buffer as Stage1, its clip levels are those of the white balanced channels
+    white_balance: Subbuffer<[u8]>,
+    // Same buffer as LensShading, which runs first and scales the clip levels by the local gains
+    shading_gains: Subbuffer<[u8]>,
+    map_size: [i32; 2],
+
+    extent: [u32; 3],
+}
+
+// Filters noise out of the normalized mosaic after Stage1
+struct Denoise {
+    // Noise model of each channel of the shifted mosaic in normalized units, the shader scales
+    // it by the gains of the white balance
+    scale: [f32; 4],
+    offset: [f32; 4],
+    strength: f32,
+    white_balance: Subbuffer<[u8]>,
+
+    extent: [u32; 3],
+}
+
+// Applies the lens shading map between Stage1 and Stage2
+struct LensShading {
+    shading_gains: Subbuffer<[u8]>,
+    map_size: [i32; 2],
+
+    extent: [u32; 3],
+}
+
 struct Stage2 {
+    algorithm: DemosaicAlgorithm,
+    // Of the RGB intermediates from here on, the following stages pick it up from their input
+    format: Format,
+
+    extent: [u32; 3],
+}
+
+struct Stage3 {
+    // Camera space (as produced by Stage1) to linear RGB of the output color space
+    camera_to_rgb: [f32; 9],
+}
+
+// Compresses the dynamic range between Stage3 and Stage4
+struct ToneMap<'a> {
+    operator: &'a ToneMapOperator,
+    exposure_bias: f32,
+    // Luminance coefficients of the output primaries
+    luminance: [f32; 3],
+    // Peak of the output relative to reference white
+    headroom: f32,
+}
+
+struct Stage4 {
+    color_space: ColorSpace,
+}
+
+struct Stage5 {
+    format: OutputFormat,
     extent: [u32; 3],
+
+    // Written by the shader and read back by the host once the pipeline has completed, rounded
+    // up to whole words
+    output_buffer: Subbuffer<[u8]>,
+}
+
+impl Stage5 {
+    fn new(
+        context: &context::Context,
+        format: OutputFormat,
+        extent: [u32; 3],
+    ) -> Result<Stage5, RawProcessorError> {
+        let size = output_size(format, extent);
+
+        let output_buffer = context
+            .pool
+            .readback_buffer(size.next_multiple_of(4), BufferUsage::STORAGE_BUFFER)?;
+
+        Ok(Stage5 {
+            format,
+            extent,
+            output_buffer,
+        })
+    }
+
+    // Tightly packed output without the padding of the last word
+    fn output(&self) -> Subbuffer<[u8]> {
+        self.output_buffer
+            .clone()
+            .slice(..output_size(self.format, self.extent))
+    }
+
+    fn word_count(&self) -> u32 {
+        (self.output_buffer.len() / 4) as u32
+    }
+
+    // One invocation per word, laid out over rows as wide as the image
+    fn work_groups(&self) -> [u32; 3] {
+        let width = self.extent[0];
+        stage::work_groups([width, self.word_count().div_ceil(width), 1])
+    }
+}
+
+impl StageInPipeline for Unpack<'_> {
+    fn create_stage_resources(
+        &self,
+        context: &context::Context,
+        _: Option<StageOutput>,
+    ) -> Result<StageResources, RawProcessorError> {
+        let packed_buffer = {
+            // The shader reads whole 32-bit words
+            let buffer = context.pool.buffer(
+                self.buffer.len().next_multiple_of(4) as DeviceSize,
+                BufferUsage::STORAGE_BUFFER,
+            )?;
+
+            buffer.write()?[..self.buffer.len()].copy_from_slice(self.buffer);
+
+            buffer
+        };
+
+        let (_, raw_image_view) = {
+            let image = context
+                .pool
+                .image(Format::R16_UINT, self.extent, ImageUsage::STORAGE)?;
+
+            let view = ImageView::new_default(image.clone())?;
+
+            (image, view)
+        };
+
+        let compute_pipeline = context.pipelines.get(Shader::Unpack);
+
+        let layout = compute_pipeline.layout().set_layouts().get(0).unwrap();
+        let descriptor_set = DescriptorSet::new(
+            context.descriptor_set_allocator.clone(),
+            layout.clone(),
+            [
+                WriteDescriptorSet::buffer(0, packed_buffer.clone()),
+                WriteDescriptorSet::image_view(1, raw_image_view.clone()),
+            ],
+            [],
+        )?;
+
+        Ok(StageResources {
+            compute_pipeline,
+            descriptor_set,
+            image_views: vec![raw_image_view],
+            buffers: vec![packed_buffer],
+        })
+    }
+
+    fn bind_stage_pipeline_and_dispatch(
+        &self,
+        command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        resources: &StageResources,
+        work_groups: [u32; 3],
+    ) -> Result<(), RawProcessorError> {
+        #[derive(BufferContents)]
+        #[repr(C)]
+        struct Constants {
+            bits_per_sample: u32,
+            row_stride: u32,
+        }
+
+        let constants = Constants {
+            bits_per_sample: self.format.bits_per_sample(),
+            row_stride: self.row_stride,
+        };
+
+        command_buffer_builder
+            .bind_pipeline_compute(resources.compute_pipeline.clone())?
+            .push_constants(resources.compute_pipeline.layout().clone(), 0, constants)?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                resources.compute_pipeline.layout().clone(),
+                0,
+                resources.descriptor_set.clone(),
+            )?;
+
+        unsafe {
+            command_buffer_builder.dispatch(work_groups)?;
+        }
+
+        Ok(())
+    }
 }
 
-struct Stage3 {
-    // forward_matrix_1: [f32; 9],
-    // forward_matrix_2: [f32; 9],
-    color_correction_transform: [f32; 9],
-    // neutral_point: [f32; 3],
-}
+impl StageInPipeline for Stage0<'_> {
+    fn create_stage_resources(
+        &self,
+        context: &context::Context,
+        input: Option<StageOutput>,
+    ) -> Result<StageResources, RawProcessorError> {
+        let (raw_buffers, raw_image_view) = match input {
+            // Already unpacked on the GPU
+            Some(input) => (vec![], input.image_views.get(0).unwrap().clone()),
+            None => {
+                // Lock subbufer and copy the entire RAW data into it, padded rows included. Packed
+                // samples are unpacked on the host when the Unpack stage could not be used.
+                let buffer = if self.format.is_packed() {
+                    let buffer = context.pool.buffer(
+                        (self.extent[0] * self.extent[1] * 2) as DeviceSize,
+                        BufferUsage::TRANSFER_SRC,
+                    )?;
+
+                    unpack::unpack(
+                        self.format,
+                        self.buffer,
+                        self.extent[0],
+                        self.row_stride as usize,
+                        &mut buffer.write()?,
+                    );
+
+                    buffer
+                } else {
+                    let buffer = context
+                        .pool
+                        .buffer(self.buffer.len() as DeviceSize, BufferUsage::TRANSFER_SRC)?;
+
+                    buffer.write()?.copy_from_slice(self.buffer);
+
+                    buffer
+                };
+
+                let image = context.pool.image(
+                    Format::R16_UINT,
+                    self.extent,
+                    ImageUsage::STORAGE | ImageUsage::TRANSFER_DST,
+                )?;
+
+                let view = ImageView::new_default(image.clone())?;
+
+                (vec![buffer], view)
+            }
+        };
+
+        let (_, raw_shifted_image_view) = {
+            let image = context
+                .pool
+                .image(Format::R16_UINT, self.extent, ImageUsage::STORAGE)?;
+
+            let view = ImageView::new_default(image.clone())?;
+
+            (image, view)
+        };
+
+        let compute_pipeline = context.pipelines.get(Shader::ShiftBayer);
+
+        let layout = compute_pipeline.layout().set_layouts().get(0).unwrap();
+        let descriptor_set = DescriptorSet::new(
+            context.descriptor_set_allocator.clone(),
+            layout.clone(),
+            [
+                WriteDescriptorSet::image_view(0, raw_image_view.clone()),
+                WriteDescriptorSet::image_view(1, raw_shifted_image_view.clone()),
+            ],
+            [],
+        )?;
+
+        Ok(StageResources {
+            compute_pipeline,
+            descriptor_set,
+            image_views: vec![raw_shifted_image_view, raw_image_view],
+            buffers: raw_buffers,
+        })
+    }
+
+    fn bind_stage_pipeline_and_dispatch(
+        &self,
+        command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        resources: &StageResources,
+        work_groups: [u32; 3],
+    ) -> Result<(), RawProcessorError> {
+        #[derive(BufferContents)]
+        #[repr(C)]
+        struct Constants {
+            shift_vector: [i32; 2],
+        }
+
+        let constants = Constants {
+            shift_vector: shift_vector(self.color_filter_arrangement),
+        };
+
+        // Upload the RAW data, the dispatch below waits for the copy to complete
+        if let Some(buffer) = resources.buffers.first() {
+            let mut copy_info = CopyBufferToImageInfo::buffer_image(
+                buffer.clone(),
+                resources.image_views[1].image().clone(),
+            );
+
+            // Skip the padding at the end of each row, samples unpacked on the host are tight
+            if !self.format.is_packed() {
+                copy_info.regions[0].buffer_row_length = self.row_stride / 2;
+            }
+
+            command_buffer_builder.copy_buffer_to_image(copy_info)?;
+        }
+
+        command_buffer_builder
+            .bind_pipeline_compute(resources.compute_pipeline.clone())?
+            .push_constants(resources.compute_pipeline.layout().clone(), 0, constants)?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                resources.compute_pipeline.layout().clone(),
+                0,
+                resources.descriptor_set.clone(),
+            )?;
+
+        unsafe {
+            command_buffer_builder.dispatch(work_groups)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl StageInPipeline for DefectCorrection<'_> {
+    fn create_stage_resources(
+        &self,
+        context: &context::Context,
+        input: Option<StageOutput>,
+    ) -> Result<StageResources, RawProcessorError> {
+        let mask_buffer = {
+            let mask = defect_mask(self.bad_pixels, self.shift_vector, self.extent);
+
+            let buffer = context
+                .pool
+                .buffer((mask.len() * 4) as DeviceSize, BufferUsage::STORAGE_BUFFER)?;
+
+            let mut data = buffer.write()?;
+            for (bytes, word) in data.chunks_exact_mut(4).zip(mask) {
+                bytes.copy_from_slice(&word.to_ne_bytes());
+            }
+            drop(data);
+
+            buffer
+        };
+
+        let (_, raw_corrected_image_view) = {
+            let image = context
+                .pool
+                .image(Format::R16_UINT, self.extent, ImageUsage::STORAGE)?;
+
+            let view = ImageView::new_default(image.clone())?;
+
+            (image, view)
+        };
+
+        let compute_pipeline = context.pipelines.get(Shader::DefectCorrection);
+
+        let layout = compute_pipeline.layout().set_layouts().get(0).unwrap();
+        let descriptor_set = DescriptorSet::new(
+            context.descriptor_set_allocator.clone(),
+            layout.clone(),
+            [
+                WriteDescriptorSet::image_view(
+                    0,
+                    input.unwrap().image_views.get(0).unwrap().clone(),
+                ),
+                WriteDescriptorSet::image_view(1, raw_corrected_image_view.clone()),
+                WriteDescriptorSet::buffer(2, mask_buffer.clone()),
+            ],
+            [],
+        )?;
+
+        Ok(StageResources {
+            compute_pipeline,
+            descriptor_set,
+            image_views: vec![raw_corrected_image_view],
+            buffers: vec![mask_buffer],
+        })
+    }
+
+    fn bind_stage_pipeline_and_dispatch(
+        &self,
+        command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        resources: &StageResources,
+        work_groups: [u32; 3],
+    ) -> Result<(), RawProcessorError> {
+        #[derive(BufferContents)]
+        #[repr(C)]
+        struct Constants {
+            threshold: f32,
+        }
+
+        let constants = Constants {
+            threshold: self.threshold,
+        };
+
+        command_buffer_builder
+            .bind_pipeline_compute(resources.compute_pipeline.clone())?
+            .push_constants(resources.compute_pipeline.layout().clone(), 0, constants)?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                resources.compute_pipeline.layout().clone(),
+                0,
+                resources.descriptor_set.clone(),
+            )?;
+
+        unsafe {
+            command_buffer_builder.dispatch(work_groups)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl StageInPipeline for Stage1 {
+    fn create_stage_resources(
+        &self,
+        context: &context::Context,
+        input: Option<StageOutput>,
+    ) -> Result<StageResources, RawProcessorError> {
+        let (_, raw_normalized_image_view) = {
+            let image = context
+                .pool
+                .image(Format::R16_SFLOAT, self.extent, ImageUsage::STORAGE)?;
+
+            let view = ImageView::new_default(image.clone())?;
+
+            (image, view)
+        };
+
+        let compute_pipeline = context.pipelines.get(Shader::Normalize);
+
+        let layout = compute_pipeline.layout().set_layouts().get(0).unwrap();
+        let descriptor_set = DescriptorSet::new(
+            context.descriptor_set_allocator.clone(),
+            layout.clone(),
+            [
+                WriteDescriptorSet::image_view(
+                    0,
+                    input.unwrap().image_views.get(0).unwrap().clone(),
+                ),
+                WriteDescriptorSet::image_view(1, raw_normalized_image_view.clone()),
+                WriteDescriptorSet::buffer(2, self.white_balance.clone()),
+            ],
+            [],
+        )?;
+
+        Ok(StageResources {
+            compute_pipeline,
+            descriptor_set,
+            image_views: vec![raw_normalized_image_view],
+            buffers: vec![],
+        })
+    }
+
+    fn bind_stage_pipeline_and_dispatch(
+        &self,
+        command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        resources: &StageResources,
+        work_groups: [u32; 3],
+    ) -> Result<(), RawProcessorError> {
+        #[derive(BufferContents)]
+        #[repr(C)]
+        struct Constants {
+            black_level: [i32; 4],
+            white_level: i32,
+        }
+
+        let constants = Constants {
+            white_level: self.white_level,
+            black_level: self.black_level,
+        };
+
+        command_buffer_builder
+            .bind_pipeline_compute(resources.compute_pipeline.clone())?
+            .push_constants(resources.compute_pipeline.layout().clone(), 0, constants)?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                resources.compute_pipeline.layout().clone(),
+                0,
+                resources.descriptor_set.clone(),
+            )?;
+
+        unsafe {
+            command_buffer_builder.dispatch(work_groups)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl StageInPipeline for HighlightReconstruction {
+    fn create_stage_resources(
+        &self,
+        context: &context::Context,
+        input: Option<StageOutput>,
+    ) -> Result<StageResources, RawProcessorError> {
+        let (_, raw_reconstructed_image_view) = {
+            let image = context
+                .pool
+                .image(Format::R16_SFLOAT, self.extent, ImageUsage::STORAGE)?;
+
+            let view = ImageView::new_default(image.clone())?;
+
+            (image, view)
+        };
+
+        let compute_pipeline = context.pipelines.get(Shader::Highlights);
+
+        let layout = compute_pipeline.layout().set_layouts().get(0).unwrap();
+        let descriptor_set = DescriptorSet::new(
+            context.descriptor_set_allocator.clone(),
+            layout.clone(),
+            [
+                WriteDescriptorSet::image_view(
+                    0,
+                    input.unwrap().image_views.get(0).unwrap().clone(),
+                ),
+                WriteDescriptorSet::image_view(1, raw_reconstructed_image_view.clone()),
+                WriteDescriptorSet::buffer(2, self.white_balance.clone()),
+                WriteDescriptorSet::buffer(3, self.shading_gains.clone()),
+            ],
+            [],
+        )?;
+
+        Ok(StageResources {
+            compute_pipeline,
+            descriptor_set,
+            image_views: vec![raw_reconstructed_image_view],
+            buffers: vec![self.shading_gains.clone()],
+        })
+    }
+
+    fn bind_stage_pipeline_and_dispatch(
+        &self,
+        command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        resources: &StageResources,
+        work_groups: [u32; 3],
+    ) -> Result<(), RawProcessorError> {
+        #[derive(BufferContents)]
+        #[repr(C)]
+        struct Constants {
+            map_size: [i32; 2],
+            mode: u32,
+        }
+
+        let constants = Constants {
+            map_size: self.map_size,
+            mode: match self.mode {
+                HighlightMode::Clip => 0,
+                HighlightMode::Blend => 1,
+                HighlightMode::Inpaint => 2,
+            },
+        };
+
+        command_buffer_builder
+            .bind_pipeline_compute(resources.compute_pipeline.clone())?
+            .push_constants(resources.compute_pipeline.layout().clone(), 0, constants)?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                resources.compute_pipeline.layout().clone(),
+                0,
+                resources.descriptor_set.clone(),
+            )?;
 
-struct Stage4 {}
+        // One invocation per 2x2 quad
+        unsafe {
+            command_buffer_builder.dispatch([
+                work_groups[0].div_ceil(2),
+                work_groups[1].div_ceil(2),
+                1,
+            ])?;
+        }
 
-struct Stage5 {
-    extent: [u32; 3],
+        Ok(())
+    }
 }
 
-impl StageInPipeline for Stage0 {
+impl StageInPipeline for Denoise {
     fn create_stage_resources(
         &self,
         context: &context::Context,
-        _: Option<StageOutput>,
-    ) -> StageResources {
-        let (_, raw_image_view) = {
-            let buffer = Buffer::new_slice::<u8>(
-                context.memory_allocator.clone(),
-                BufferCreateInfo {
-                    usage: BufferUsage::TRANSFER_SRC,
-                    ..Default::default()
-                },
-                AllocationCreateInfo {
-                    memory_type_filter: MemoryTypeFilter::PREFER_HOST
-                        | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
-                    ..Default::default()
-                },
-                self.buffer_len as DeviceSize,
-            )
-            .unwrap();
-
-            // Lock subbufer and copy the entire RAW data into it
-            buffer
-                .write()
-                .expect("Failed to lock subbufer for writing")
-                .copy_from_slice(unsafe {
-                    slice::from_raw_parts(self.buffer, self.buffer_len as usize)
-                });
-
-            let image = Image::new(
-                context.memory_allocator.clone(),
-                ImageCreateInfo {
-                    format: Format::R16_UINT,
-                    extent: self.extent,
-                    usage: ImageUsage::STORAGE | ImageUsage::TRANSFER_DST,
-                    ..Default::default()
-                },
-                AllocationCreateInfo::default(),
-            )
-            .unwrap();
-
-            let view = ImageView::new_default(image.clone()).unwrap();
-
-            let mut command_buffer_builder = AutoCommandBufferBuilder::primary(
-                context.command_buffer_allocator.clone(),
-                context.queue.queue_family_index(),
-                CommandBufferUsage::OneTimeSubmit,
-            )
-            .unwrap();
-
-            command_buffer_builder
-                .copy_buffer_to_image(CopyBufferToImageInfo::buffer_image(buffer, image.clone()))
-                .unwrap();
-
-            let command_buffer = command_buffer_builder.build().unwrap();
-
-            command_buffer
-                .execute(context.queue.clone())
-                .unwrap()
-                .then_signal_fence_and_flush()
-                .unwrap()
-                .wait(None)
-                .unwrap();
-
-            (image, view)
-        };
-
-        let (_, raw_shifted_image_view) = {
-            let image = Image::new(
-                context.memory_allocator.clone(),
-                ImageCreateInfo {
-                    format: Format::R16_UINT,
-                    extent: self.extent,
-                    usage: ImageUsage::STORAGE,
-                    ..Default::default()
-                },
-                AllocationCreateInfo::default(),
-            )
-            .unwrap();
+        input: Option<StageOutput>,
+    ) -> Result<StageResources, RawProcessorError> {
+        let (_, raw_denoised_image_view) = {
+            let image = context
+                .pool
+                .image(Format::R16_SFLOAT, self.extent, ImageUsage::STORAGE)?;
 
-            let view = ImageView::new_default(image.clone()).unwrap();
+            let view = ImageView::new_default(image.clone())?;
 
             (image, view)
         };
 
-        mod cs {
-            vulkano_shaders::shader! {
-                bytes: "shaders/shiftbayer.spv"
-            }
-        }
-
-        let compute_shader = cs::load(context.device.clone()).unwrap();
-        let stage = PipelineShaderStageCreateInfo::new(compute_shader.entry_point("main").unwrap());
-        let layout = PipelineLayout::new(
-            context.device.clone(),
-            PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
-                .into_pipeline_layout_create_info(context.device.clone())
-                .unwrap(),
-        )
-        .unwrap();
-
-        let compute_pipeline = ComputePipeline::new(
-            context.device.clone(),
-            None,
-            ComputePipelineCreateInfo::stage_layout(stage, layout),
-        )
-        .expect("Failed to create compute pipeline");
+        let compute_pipeline = context.pipelines.get(Shader::Denoise);
 
         let layout = compute_pipeline.layout().set_layouts().get(0).unwrap();
         let descriptor_set = DescriptorSet::new(
             context.descriptor_set_allocator.clone(),
             layout.clone(),
             [
-                WriteDescriptorSet::image_view(0, raw_image_view.clone()),
-                WriteDescriptorSet::image_view(1, raw_shifted_image_view.clone()),
+                WriteDescriptorSet::image_view(
+                    0,
+                    input.unwrap().image_views.get(0).unwrap().clone(),
+                ),
+                WriteDescriptorSet::image_view(1, raw_denoised_image_view.clone()),
+                WriteDescriptorSet::buffer(2, self.white_balance.clone()),
             ],
             [],
-        )
-        .unwrap();
+        )?;
 
-        StageResources {
+        Ok(StageResources {
             compute_pipeline,
             descriptor_set,
-            image_views: vec![raw_image_view, raw_shifted_image_view],
+            image_views: vec![raw_denoised_image_view],
             buffers: vec![],
-            commands: vec![],
-        }
+        })
     }
 
     fn bind_stage_pipeline_and_dispatch(
@@ -195,88 +781,56 @@ impl StageInPipeline for Stage0 {
         command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
         resources: &StageResources,
         work_groups: [u32; 3],
-    ) {
+    ) -> Result<(), RawProcessorError> {
         #[derive(BufferContents)]
         #[repr(C)]
         struct Constants {
-            shift_vector: [i32; 2],
+            scale: [f32; 4],
+            offset: [f32; 4],
+            strength: f32,
         }
 
-        let shift_vector = match self.color_filter_arrangement {
-            0 /* RGGB */ => [0, 0],
-            1 /* GRBG */ => [1, 0],
-            2 /* GBRG */ => [0, 1],
-            3 /* BGGR */ => [1, 1],
-            _ => [0, 0],
+        let constants = Constants {
+            scale: self.scale,
+            offset: self.offset,
+            strength: self.strength,
         };
 
-        let constants = Constants { shift_vector };
-
         command_buffer_builder
-            .bind_pipeline_compute(resources.compute_pipeline.clone())
-            .unwrap()
-            .push_constants(resources.compute_pipeline.layout().clone(), 0, constants)
-            .unwrap()
+            .bind_pipeline_compute(resources.compute_pipeline.clone())?
+            .push_constants(resources.compute_pipeline.layout().clone(), 0, constants)?
             .bind_descriptor_sets(
                 PipelineBindPoint::Compute,
                 resources.compute_pipeline.layout().clone(),
                 0,
                 resources.descriptor_set.clone(),
-            )
-            .unwrap();
+            )?;
 
         unsafe {
-            command_buffer_builder.dispatch(work_groups).unwrap();
+            command_buffer_builder.dispatch(work_groups)?;
         }
+
+        Ok(())
     }
 }
 
-impl StageInPipeline for Stage1 {
+impl StageInPipeline for LensShading {
     fn create_stage_resources(
         &self,
         context: &context::Context,
         input: Option<StageOutput>,
-    ) -> StageResources {
-        let (_, raw_normalized_image_view) = {
-            let image = Image::new(
-                context.memory_allocator.clone(),
-                ImageCreateInfo {
-                    format: Format::R16_SFLOAT,
-                    extent: self.extent,
-                    usage: ImageUsage::STORAGE,
-                    ..Default::default()
-                },
-                AllocationCreateInfo::default(),
-            )
-            .unwrap();
+    ) -> Result<StageResources, RawProcessorError> {
+        let (_, raw_shaded_image_view) = {
+            let image = context
+                .pool
+                .image(Format::R16_SFLOAT, self.extent, ImageUsage::STORAGE)?;
 
-            let view = ImageView::new_default(image.clone()).unwrap();
+            let view = ImageView::new_default(image.clone())?;
 
             (image, view)
         };
 
-        mod cs {
-            vulkano_shaders::shader! {
-                bytes: "shaders/normalize.spv"
-            }
-        }
-
-        let compute_shader = cs::load(context.device.clone()).unwrap();
-        let stage = PipelineShaderStageCreateInfo::new(compute_shader.entry_point("main").unwrap());
-        let layout = PipelineLayout::new(
-            context.device.clone(),
-            PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
-                .into_pipeline_layout_create_info(context.device.clone())
-                .unwrap(),
-        )
-        .unwrap();
-
-        let compute_pipeline = ComputePipeline::new(
-            context.device.clone(),
-            None,
-            ComputePipelineCreateInfo::stage_layout(stage, layout),
-        )
-        .expect("Failed to create compute pipeline");
+        let compute_pipeline = context.pipelines.get(Shader::LensShading);
 
         let layout = compute_pipeline.layout().set_layouts().get(0).unwrap();
         let descriptor_set = DescriptorSet::new(
@@ -285,21 +839,20 @@ impl StageInPipeline for Stage1 {
             [
                 WriteDescriptorSet::image_view(
                     0,
-                    input.unwrap().image_views.get(1).unwrap().clone(),
+                    input.unwrap().image_views.get(0).unwrap().clone(),
                 ),
-                WriteDescriptorSet::image_view(1, raw_normalized_image_view.clone()),
+                WriteDescriptorSet::image_view(1, raw_shaded_image_view.clone()),
+                WriteDescriptorSet::buffer(2, self.shading_gains.clone()),
             ],
             [],
-        )
-        .unwrap();
+        )?;
 
-        StageResources {
+        Ok(StageResources {
             compute_pipeline,
             descriptor_set,
-            image_views: vec![raw_normalized_image_view],
-            buffers: vec![],
-            commands: vec![],
-        }
+            image_views: vec![raw_shaded_image_view],
+            buffers: vec![self.shading_gains.clone()],
+        })
     }
 
     fn bind_stage_pipeline_and_dispatch(
@@ -307,37 +860,32 @@ impl StageInPipeline for Stage1 {
         command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
         resources: &StageResources,
         work_groups: [u32; 3],
-    ) {
+    ) -> Result<(), RawProcessorError> {
         #[derive(BufferContents)]
         #[repr(C)]
         struct Constants {
-            color_gains: [f32; 4],
-            black_level: [i32; 4],
-            white_level: i32,
+            map_size: [i32; 2],
         }
 
         let constants = Constants {
-            color_gains: self.color_gains,
-            white_level: self.white_level,
-            black_level: self.black_level,
+            map_size: self.map_size,
         };
 
         command_buffer_builder
-            .bind_pipeline_compute(resources.compute_pipeline.clone())
-            .unwrap()
-            .push_constants(resources.compute_pipeline.layout().clone(), 0, constants)
-            .unwrap()
+            .bind_pipeline_compute(resources.compute_pipeline.clone())?
+            .push_constants(resources.compute_pipeline.layout().clone(), 0, constants)?
             .bind_descriptor_sets(
                 PipelineBindPoint::Compute,
                 resources.compute_pipeline.layout().clone(),
                 0,
                 resources.descriptor_set.clone(),
-            )
-            .unwrap();
+            )?;
 
         unsafe {
-            command_buffer_builder.dispatch(work_groups).unwrap();
+            command_buffer_builder.dispatch(work_groups)?;
         }
+
+        Ok(())
     }
 }
 
@@ -346,70 +894,57 @@ impl StageInPipeline for Stage2 {
         &self,
         context: &context::Context,
         input: Option<StageOutput>,
-    ) -> StageResources {
+    ) -> Result<StageResources, RawProcessorError> {
         let (_, rgb_image_view) = {
-            let image = Image::new(
-                context.memory_allocator.clone(),
-                ImageCreateInfo {
-                    format: Format::R16G16B16A16_SFLOAT,
-                    extent: self.extent,
-                    usage: ImageUsage::STORAGE,
-                    ..Default::default()
-                },
-                AllocationCreateInfo::default(),
-            )
-            .unwrap();
+            let image = context
+                .pool
+                .image(self.format, self.extent, ImageUsage::STORAGE)?;
 
-            let view = ImageView::new_default(image.clone()).unwrap();
+            let view = ImageView::new_default(image.clone())?;
 
             (image, view)
         };
 
-        mod cs {
-            vulkano_shaders::shader! {
-                bytes: "shaders/demosaic.spv"
+        let compute_pipeline = context.pipelines.get(
+            match self.algorithm {
+                DemosaicAlgorithm::Bilinear => Shader::DemosaicBilinear,
+                DemosaicAlgorithm::MalvarHeCutler => Shader::DemosaicMhc,
+                DemosaicAlgorithm::Menon => Shader::DemosaicMenon,
             }
+            .for_format(self.format),
+        );
+
+        let mut descriptor_writes = vec![
+            WriteDescriptorSet::image_view(0, input.unwrap().image_views.get(0).unwrap().clone()),
+            WriteDescriptorSet::image_view(1, rgb_image_view.clone()),
+        ];
+
+        // Green plane and interpolation direction shared between the two Menon passes
+        if self.algorithm == DemosaicAlgorithm::Menon {
+            let image = context
+                .pool
+                .image(self.format, self.extent, ImageUsage::STORAGE)?;
+
+            descriptor_writes.push(WriteDescriptorSet::image_view(
+                2,
+                ImageView::new_default(image)?,
+            ));
         }
 
-        let compute_shader = cs::load(context.device.clone()).unwrap();
-        let stage = PipelineShaderStageCreateInfo::new(compute_shader.entry_point("main").unwrap());
-        let layout = PipelineLayout::new(
-            context.device.clone(),
-            PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
-                .into_pipeline_layout_create_info(context.device.clone())
-                .unwrap(),
-        )
-        .unwrap();
-
-        let compute_pipeline = ComputePipeline::new(
-            context.device.clone(),
-            None,
-            ComputePipelineCreateInfo::stage_layout(stage, layout),
-        )
-        .expect("Failed to create compute pipeline");
-
         let layout = compute_pipeline.layout().set_layouts().get(0).unwrap();
         let descriptor_set = DescriptorSet::new(
             context.descriptor_set_allocator.clone(),
             layout.clone(),
-            [
-                WriteDescriptorSet::image_view(
-                    0,
-                    input.unwrap().image_views.get(0).unwrap().clone(),
-                ),
-                WriteDescriptorSet::image_view(1, rgb_image_view.clone()),
-            ],
+            descriptor_writes,
             [],
-        )
-        .unwrap();
+        )?;
 
-        StageResources {
+        Ok(StageResources {
             compute_pipeline,
             descriptor_set,
             image_views: vec![rgb_image_view],
             buffers: vec![],
-            commands: vec![],
-        }
+        })
     }
 
     fn bind_stage_pipeline_and_dispatch(
@@ -417,33 +952,57 @@ impl StageInPipeline for Stage2 {
         command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
         resources: &StageResources,
         work_groups: [u32; 3],
-    ) {
+    ) -> Result<(), RawProcessorError> {
         #[derive(BufferContents)]
         #[repr(C)]
         struct Constants {
             size: [i32; 2],
         }
 
-        let constants = Constants {
-            size: [self.extent[0] as i32, self.extent[1] as i32],
-        };
+        #[derive(BufferContents)]
+        #[repr(C)]
+        struct MenonConstants {
+            size: [i32; 2],
+            pass_index: u32,
+        }
+
+        let size = [self.extent[0] as i32, self.extent[1] as i32];
 
         command_buffer_builder
-            .bind_pipeline_compute(resources.compute_pipeline.clone())
-            .unwrap()
-            .push_constants(resources.compute_pipeline.layout().clone(), 0, constants)
-            .unwrap()
+            .bind_pipeline_compute(resources.compute_pipeline.clone())?
             .bind_descriptor_sets(
                 PipelineBindPoint::Compute,
                 resources.compute_pipeline.layout().clone(),
                 0,
                 resources.descriptor_set.clone(),
-            )
-            .unwrap();
+            )?;
 
-        unsafe {
-            command_buffer_builder.dispatch(work_groups).unwrap();
+        if self.algorithm == DemosaicAlgorithm::Menon {
+            // Green first, then red and blue from the completed green plane
+            for pass_index in 0..2 {
+                command_buffer_builder.push_constants(
+                    resources.compute_pipeline.layout().clone(),
+                    0,
+                    MenonConstants { size, pass_index },
+                )?;
+
+                unsafe {
+                    command_buffer_builder.dispatch(work_groups)?;
+                }
+            }
+        } else {
+            command_buffer_builder.push_constants(
+                resources.compute_pipeline.layout().clone(),
+                0,
+                Constants { size },
+            )?;
+
+            unsafe {
+                command_buffer_builder.dispatch(work_groups)?;
+            }
         }
+
+        Ok(())
     }
 }
 
@@ -452,29 +1011,10 @@ impl StageInPipeline for Stage3 {
         &self,
         context: &context::Context,
         input: Option<StageOutput>,
-    ) -> StageResources {
-        mod cs {
-            vulkano_shaders::shader! {
-                bytes: "shaders/colorcorrection.spv"
-            }
-        }
-
-        let compute_shader = cs::load(context.device.clone()).unwrap();
-        let stage = PipelineShaderStageCreateInfo::new(compute_shader.entry_point("main").unwrap());
-        let layout = PipelineLayout::new(
-            context.device.clone(),
-            PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
-                .into_pipeline_layout_create_info(context.device.clone())
-                .unwrap(),
-        )
-        .unwrap();
-
-        let compute_pipeline = ComputePipeline::new(
-            context.device.clone(),
-            None,
-            ComputePipelineCreateInfo::stage_layout(stage, layout),
-        )
-        .expect("Failed to create compute pipeline");
+    ) -> Result<StageResources, RawProcessorError> {
+        let compute_pipeline = context.pipelines.get(
+            Shader::ColorCorrection.for_format(input.as_ref().unwrap().image_views[0].format()),
+        );
 
         let layout = compute_pipeline.layout().set_layouts().get(0).unwrap();
         let descriptor_set = DescriptorSet::new(
@@ -485,16 +1025,14 @@ impl StageInPipeline for Stage3 {
                 input.as_ref().unwrap().image_views.get(0).unwrap().clone(),
             )],
             [],
-        )
-        .unwrap();
+        )?;
 
-        StageResources {
+        Ok(StageResources {
             compute_pipeline,
             descriptor_set,
             image_views: input.unwrap().image_views,
             buffers: vec![],
-            commands: vec![],
-        }
+        })
     }
 
     fn bind_stage_pipeline_and_dispatch(
@@ -502,147 +1040,104 @@ impl StageInPipeline for Stage3 {
         command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
         resources: &StageResources,
         work_groups: [u32; 3],
-    ) {
+    ) -> Result<(), RawProcessorError> {
         #[derive(BufferContents)]
         #[repr(C)]
         struct Constants {
-            // forward_matrix_1: [[f32; 4]; 3],
-            // forward_matrix_2: [[f32; 4]; 3],
-            color_correction_transform: [[f32; 4]; 3],
-            // neutral_point: [f32; 3],
+            camera_to_rgb: [[f32; 4]; 3],
         }
 
         let constants = Constants {
-            // forward_matrix_1: [
-            //     [
-            //         self.forward_matrix_1[0],
-            //         self.forward_matrix_1[1],
-            //         self.forward_matrix_1[2],
-            //         0.0, /* padding */
-            //     ],
-            //     [
-            //         self.forward_matrix_1[3],
-            //         self.forward_matrix_1[4],
-            //         self.forward_matrix_1[5],
-            //         0.0, /* padding */
-            //     ],
-            //     [
-            //         self.forward_matrix_1[6],
-            //         self.forward_matrix_1[7],
-            //         self.forward_matrix_1[8],
-            //         0.0, /* padding */
-            //     ],
-            // ],
-            // forward_matrix_2: [
-            //     [
-            //         self.forward_matrix_2[0],
-            //         self.forward_matrix_2[1],
-            //         self.forward_matrix_2[2],
-            //         0.0, /* padding2*/
-            //     ],
-            //     [
-            //         self.forward_matrix_2[3],
-            //         self.forward_matrix_2[4],
-            //         self.forward_matrix_2[5],
-            //         0.0, /* padding2*/
-            //     ],
-            //     [
-            //         self.forward_matrix_2[6],
-            //         self.forward_matrix_2[7],
-            //         self.forward_matrix_2[8],
-            //         0.0, /* padding */
-            //     ],
-            // ],
-            color_correction_transform: [
+            camera_to_rgb: [
                 [
-                    self.color_correction_transform[0],
-                    self.color_correction_transform[1],
-                    self.color_correction_transform[2],
-                    0.0, /* padding2*/
+                    self.camera_to_rgb[0],
+                    self.camera_to_rgb[1],
+                    self.camera_to_rgb[2],
+                    0.0, /* padding */
                 ],
                 [
-                    self.color_correction_transform[3],
-                    self.color_correction_transform[4],
-                    self.color_correction_transform[5],
-                    0.0, /* padding2*/
+                    self.camera_to_rgb[3],
+                    self.camera_to_rgb[4],
+                    self.camera_to_rgb[5],
+                    0.0, /* padding */
                 ],
                 [
-                    self.color_correction_transform[6],
-                    self.color_correction_transform[7],
-                    self.color_correction_transform[8],
+                    self.camera_to_rgb[6],
+                    self.camera_to_rgb[7],
+                    self.camera_to_rgb[8],
                     0.0, /* padding */
                 ],
             ],
-            // neutral_point: self.neutral_point,
         };
 
         command_buffer_builder
-            .bind_pipeline_compute(resources.compute_pipeline.clone())
-            .unwrap()
-            .push_constants(resources.compute_pipeline.layout().clone(), 0, constants)
-            .unwrap()
+            .bind_pipeline_compute(resources.compute_pipeline.clone())?
+            .push_constants(resources.compute_pipeline.layout().clone(), 0, constants)?
             .bind_descriptor_sets(
                 PipelineBindPoint::Compute,
                 resources.compute_pipeline.layout().clone(),
                 0,
                 resources.descriptor_set.clone(),
-            )
-            .unwrap();
+            )?;
 
         unsafe {
-            command_buffer_builder.dispatch(work_groups).unwrap();
+            command_buffer_builder.dispatch(work_groups)?;
         }
+
+        Ok(())
     }
 }
 
-impl StageInPipeline for Stage4 {
+impl StageInPipeline for ToneMap<'_> {
     fn create_stage_resources(
         &self,
         context: &context::Context,
         input: Option<StageOutput>,
-    ) -> StageResources {
-        mod cs {
-            vulkano_shaders::shader! {
-                bytes: "shaders/gammacorrection.spv"
+    ) -> Result<StageResources, RawProcessorError> {
+        // Bound for every operator, only read by the curve
+        let curve_buffer = {
+            let curve = match self.operator {
+                ToneMapOperator::Curve(curve) => curve.as_slice(),
+                _ => &[0.0],
+            };
+
+            let buffer = context
+                .pool
+                .buffer((curve.len() * 4) as DeviceSize, BufferUsage::STORAGE_BUFFER)?;
+
+            let mut data = buffer.write()?;
+            for (bytes, value) in data.chunks_exact_mut(4).zip(curve) {
+                bytes.copy_from_slice(&value.to_ne_bytes());
             }
-        }
+            drop(data);
 
-        let compute_shader = cs::load(context.device.clone()).unwrap();
-        let stage = PipelineShaderStageCreateInfo::new(compute_shader.entry_point("main").unwrap());
-        let layout = PipelineLayout::new(
-            context.device.clone(),
-            PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
-                .into_pipeline_layout_create_info(context.device.clone())
-                .unwrap(),
-        )
-        .unwrap();
+            buffer
+        };
 
-        let compute_pipeline = ComputePipeline::new(
-            context.device.clone(),
-            None,
-            ComputePipelineCreateInfo::stage_layout(stage, layout),
-        )
-        .expect("Failed to create compute pipeline");
+        let compute_pipeline = context
+            .pipelines
+            .get(Shader::ToneMap.for_format(input.as_ref().unwrap().image_views[0].format()));
 
         let layout = compute_pipeline.layout().set_layouts().get(0).unwrap();
         let descriptor_set = DescriptorSet::new(
             context.descriptor_set_allocator.clone(),
             layout.clone(),
-            [WriteDescriptorSet::image_view(
-                0,
-                input.as_ref().unwrap().image_views.get(0).unwrap().clone(),
-            )],
+            [
+                WriteDescriptorSet::image_view(
+                    0,
+                    input.as_ref().unwrap().image_views.get(0).unwrap().clone(),
+                ),
+                WriteDescriptorSet::buffer(1, curve_buffer.clone()),
+            ],
             [],
-        )
-        .unwrap();
+        )?;
 
-        StageResources {
+        Ok(StageResources {
             compute_pipeline,
             descriptor_set,
             image_views: input.unwrap().image_views,
-            buffers: vec![],
-            commands: vec![],
-        }
+            buffers: vec![curve_buffer],
+        })
     }
 
     fn bind_stage_pipeline_and_dispatch(
@@ -650,101 +1145,139 @@ impl StageInPipeline for Stage4 {
         command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
         resources: &StageResources,
         work_groups: [u32; 3],
-    ) {
+    ) -> Result<(), RawProcessorError> {
+        #[derive(BufferContents)]
+        #[repr(C)]
+        struct Constants {
+            luminance: [f32; 4],
+            operator: u32,
+            exposure: f32,
+            white_point: f32,
+            curve_length: u32,
+            headroom: f32,
+        }
+
+        let (operator, white_point, curve_length) = match self.operator {
+            ToneMapOperator::Clip => (0, 1.0, 0),
+            ToneMapOperator::Aces => (1, 1.0, 0),
+            ToneMapOperator::Reinhard { white_point } => (2, *white_point, 0),
+            ToneMapOperator::Curve(curve) => (3, 1.0, curve.len() as u32),
+        };
+
+        let [red, green, blue] = self.luminance;
+
+        let constants = Constants {
+            luminance: [red, green, blue, 0.0 /* padding */],
+            operator,
+            exposure: self.exposure_bias.exp2(),
+            white_point,
+            curve_length,
+            headroom: self.headroom,
+        };
+
         command_buffer_builder
-            .bind_pipeline_compute(resources.compute_pipeline.clone())
-            .unwrap()
+            .bind_pipeline_compute(resources.compute_pipeline.clone())?
+            .push_constants(resources.compute_pipeline.layout().clone(), 0, constants)?
             .bind_descriptor_sets(
                 PipelineBindPoint::Compute,
                 resources.compute_pipeline.layout().clone(),
                 0,
                 resources.descriptor_set.clone(),
-            )
-            .unwrap();
+            )?;
 
         unsafe {
-            command_buffer_builder.dispatch(work_groups).unwrap();
+            command_buffer_builder.dispatch(work_groups)?;
         }
+
+        Ok(())
     }
 }
 
-impl StageInPipeline for Stage5 {
+impl StageInPipeline for Stage4 {
     fn create_stage_resources(
         &self,
         context: &context::Context,
         input: Option<StageOutput>,
-    ) -> StageResources {
-        let (_, quantized_image_view, quantized_buffer, copy_quantized_image_to_buffer) = {
-            let image = Image::new(
-                context.memory_allocator.clone(),
-                ImageCreateInfo {
-                    format: Format::R8G8B8A8_UNORM,
-                    extent: self.extent,
-                    usage: ImageUsage::STORAGE | ImageUsage::TRANSFER_SRC,
-                    ..Default::default()
-                },
-                AllocationCreateInfo::default(),
-            )
-            .unwrap();
+    ) -> Result<StageResources, RawProcessorError> {
+        let compute_pipeline = context.pipelines.get(
+            Shader::GammaCorrection.for_format(input.as_ref().unwrap().image_views[0].format()),
+        );
 
-            let view = ImageView::new_default(image.clone()).unwrap();
-
-            let buffer = Buffer::from_iter(
-                context.memory_allocator.clone(),
-                BufferCreateInfo {
-                    usage: BufferUsage::TRANSFER_DST,
-                    ..Default::default()
-                },
-                AllocationCreateInfo {
-                    memory_type_filter: MemoryTypeFilter::PREFER_HOST
-                        | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
-                    ..Default::default()
-                },
-                (0..self.extent[0] * self.extent[1] * 4).map(|_| 0u8),
-            )
-            .unwrap();
+        let layout = compute_pipeline.layout().set_layouts().get(0).unwrap();
+        let descriptor_set = DescriptorSet::new(
+            context.descriptor_set_allocator.clone(),
+            layout.clone(),
+            [WriteDescriptorSet::image_view(
+                0,
+                input.as_ref().unwrap().image_views.get(0).unwrap().clone(),
+            )],
+            [],
+        )?;
 
-            let mut command_buffer_builder = AutoCommandBufferBuilder::primary(
-                context.command_buffer_allocator.clone(),
-                context.queue.queue_family_index(),
-                CommandBufferUsage::OneTimeSubmit,
-            )
-            .unwrap();
+        Ok(StageResources {
+            compute_pipeline,
+            descriptor_set,
+            image_views: input.unwrap().image_views,
+            buffers: vec![],
+        })
+    }
 
-            command_buffer_builder
-                .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
-                    image.clone(),
-                    buffer.clone(),
-                ))
-                .unwrap();
+    fn bind_stage_pipeline_and_dispatch(
+        &self,
+        command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        resources: &StageResources,
+        work_groups: [u32; 3],
+    ) -> Result<(), RawProcessorError> {
+        #[derive(BufferContents)]
+        #[repr(C)]
+        struct Constants {
+            transfer_function: u32,
+            reference_white: f32,
+        }
 
-            let command_buffer = command_buffer_builder.build().unwrap();
+        // PQ is absolute up to 10000 cd/m2 with reference white at 203 cd/m2 (ITU-R BT.2408),
+        // HLG places reference white at 75% of the signal
+        let (transfer_function, reference_white) = match self.color_space {
+            ColorSpace::Linear => (0, 1.0),
+            ColorSpace::Srgb | ColorSpace::DisplayP3 => (1, 1.0),
+            ColorSpace::Rec2020 => (2, 1.0),
+            ColorSpace::ProPhoto => (3, 1.0),
+            ColorSpace::Rec2020Pq => (4, PQ_REFERENCE_WHITE / 10000.0),
+            ColorSpace::Rec2020Hlg => (5, HLG_REFERENCE_WHITE),
+        };
 
-            (image, view, buffer, command_buffer)
+        let constants = Constants {
+            transfer_function,
+            reference_white,
         };
 
-        mod cs {
-            vulkano_shaders::shader! {
-                bytes: "shaders/quantize.spv"
-            }
+        command_buffer_builder
+            .bind_pipeline_compute(resources.compute_pipeline.clone())?
+            .push_constants(resources.compute_pipeline.layout().clone(), 0, constants)?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                resources.compute_pipeline.layout().clone(),
+                0,
+                resources.descriptor_set.clone(),
+            )?;
+
+        unsafe {
+            command_buffer_builder.dispatch(work_groups)?;
         }
 
-        let compute_shader = cs::load(context.device.clone()).unwrap();
-        let stage = PipelineShaderStageCreateInfo::new(compute_shader.entry_point("main").unwrap());
-        let layout = PipelineLayout::new(
-            context.device.clone(),
-            PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
-                .into_pipeline_layout_create_info(context.device.clone())
-                .unwrap(),
-        )
-        .unwrap();
+        Ok(())
+    }
+}
 
-        let compute_pipeline = ComputePipeline::new(
-            context.device.clone(),
-            None,
-            ComputePipelineCreateInfo::stage_layout(stage, layout),
-        )
-        .expect("Failed to create compute pipeline");
+impl StageInPipeline for Stage5 {
+    fn create_stage_resources(
+        &self,
+        context: &context::Context,
+        input: Option<StageOutput>,
+    ) -> Result<StageResources, RawProcessorError> {
+        let compute_pipeline = context
+            .pipelines
+            .get(Shader::Quantize.for_format(input.as_ref().unwrap().image_views[0].format()));
 
         let layout = compute_pipeline.layout().set_layouts().get(0).unwrap();
         let descriptor_set = DescriptorSet::new(
@@ -753,21 +1286,19 @@ impl StageInPipeline for Stage5 {
             [
                 WriteDescriptorSet::image_view(
                     0,
-                    input.unwrap().image_views.get(0).unwrap().clone(),
+                    input.as_ref().unwrap().image_views.get(0).unwrap().clone(),
                 ),
-                WriteDescriptorSet::image_view(1, quantized_image_view.clone()),
+                WriteDescriptorSet::buffer(1, self.output_buffer.clone()),
             ],
             [],
-        )
-        .unwrap();
+        )?;
 
-        StageResources {
+        Ok(StageResources {
             compute_pipeline,
             descriptor_set,
-            image_views: vec![quantized_image_view],
-            buffers: vec![quantized_buffer],
-            commands: vec![copy_quantized_image_to_buffer],
-        }
+            image_views: input.unwrap().image_views,
+            buffers: vec![self.output()],
+        })
     }
 
     fn bind_stage_pipeline_and_dispatch(
@@ -775,143 +1306,511 @@ impl StageInPipeline for Stage5 {
         command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
         resources: &StageResources,
         work_groups: [u32; 3],
-    ) {
+    ) -> Result<(), RawProcessorError> {
+        #[derive(BufferContents)]
+        #[repr(C)]
+        struct Constants {
+            output_format: u32,
+            word_count: u32,
+        }
+
+        let output_format = match self.format {
+            OutputFormat::Rgba8 => 0,
+            OutputFormat::Rgb16 => 1,
+            OutputFormat::Rgba16Float => 2,
+            OutputFormat::Rgba32Float => 3,
+        };
+
+        let constants = Constants {
+            output_format,
+            word_count: self.word_count(),
+        };
+
         command_buffer_builder
-            .bind_pipeline_compute(resources.compute_pipeline.clone())
-            .unwrap()
+            .bind_pipeline_compute(resources.compute_pipeline.clone())?
+            .push_constants(resources.compute_pipeline.layout().clone(), 0, constants)?
             .bind_descriptor_sets(
                 PipelineBindPoint::Compute,
                 resources.compute_pipeline.layout().clone(),
                 0,
                 resources.descriptor_set.clone(),
-            )
-            .unwrap();
+            )?;
 
         unsafe {
-            command_buffer_builder.dispatch(work_groups).unwrap();
+            command_buffer_builder.dispatch(work_groups)?;
         }
+
+        Ok(())
+    }
+}
+
+// Reference white in cd/m2 for PQ, and the scene light HLG encodes to 75% of its signal
+const PQ_REFERENCE_WHITE: f32 = 203.0;
+const HLG_REFERENCE_WHITE: f32 = 0.265;
+// Peak luminance the PQ output is tone mapped to, in cd/m2
+const PQ_PEAK: f32 = 1000.0;
+
+fn primaries(color_space: ColorSpace) -> &'static color::Primaries {
+    match color_space {
+        ColorSpace::Srgb => &color::SRGB_PRIMARIES,
+        ColorSpace::DisplayP3 => &color::DISPLAY_P3_PRIMARIES,
+        ColorSpace::ProPhoto => &color::PROPHOTO_PRIMARIES,
+        ColorSpace::Rec2020
+        | ColorSpace::Rec2020Pq
+        | ColorSpace::Rec2020Hlg
+        | ColorSpace::Linear => &color::REC2020_PRIMARIES,
+    }
+}
+
+// Range above reference white the tone mapping keeps for the HDR transfer functions
+fn headroom(color_space: ColorSpace) -> f32 {
+    match color_space {
+        ColorSpace::Rec2020Pq => PQ_PEAK / PQ_REFERENCE_WHITE,
+        ColorSpace::Rec2020Hlg => 1.0 / HLG_REFERENCE_WHITE,
+        _ => 1.0,
+    }
+}
+
+// Half floats carry 11 significant bits, 16-bit integer and 32-bit float outputs are
+// processed in 32-bit floats from the demosaicing onward
+fn rgb_format(format: OutputFormat) -> Format {
+    match format {
+        OutputFormat::Rgba8 | OutputFormat::Rgba16Float => Format::R16G16B16A16_SFLOAT,
+        OutputFormat::Rgb16 | OutputFormat::Rgba32Float => Format::R32G32B32A32_SFLOAT,
     }
 }
 
-pub struct Finish {
-    output: Option<Subbuffer<[u8]>>,
+// Bytes of the tightly packed output
+fn output_size(format: OutputFormat, extent: [u32; 3]) -> DeviceSize {
+    (extent[0] as usize * extent[1] as usize * format.bytes_per_pixel()) as DeviceSize
+}
+
+fn new_command_buffer_builder(
+    context: &context::Context,
+) -> Result<AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>, RawProcessorError> {
+    Ok(AutoCommandBufferBuilder::primary(
+        context.command_buffer_allocator.clone(),
+        context.queue.queue_family_index(),
+        CommandBufferUsage::OneTimeSubmit,
+    )?)
 }
 
+// Fence signaled once the GPU has executed the submitted pipeline
+pub type Submission = FenceSignalFuture<CommandBufferExecFuture<NowFuture>>;
+
+pub struct Finish {}
+
 impl Finish {
     pub fn new() -> Finish {
-        Finish { output: None }
+        Finish {}
     }
 
-    pub fn finish(
-        &mut self,
+    // Records and submits the whole pipeline without waiting for it, the returned output buffer
+    // may only be read once the fence has been signaled. Frames of a burst are merged into the
+    // first one, whose metadata is used for the rest of the pipeline.
+    pub fn submit(
+        &self,
         context: &context::Context,
-        buffer: *const u8,
-        buffer_len: usize,
-        size: [i32; 2],
-        color_filter_arrangement: i32,
-        white_level: i32,
-        black_level: [i32; 4],
-        _neutral_point: [f32; 3],
-        color_gains: [f32; 4],
-        color_correction_transform: [f32; 9],
-        _forward_matrix_1: [f32; 9],
-        _forward_matrix_2: [f32; 9],
-    ) {
-        let extent = [size[0] as u32, size[1] as u32, 1];
-
-        // Shift Bayer color filter arrangement to match RGGB mosaic pattern
-        let stage0 = Stage0 {
-            color_filter_arrangement,
-            buffer,
-            buffer_len,
-            extent,
+        frames: &[RawImage],
+        params: &ProcessingParams,
+    ) -> Result<(Submission, Subbuffer<[u8]>), RawProcessorError> {
+        let image = &frames[0];
+        let extent = [image.width, image.height, 1];
+        let work_groups = stage::work_groups(extent);
+
+        let mut command_buffer_builder = new_command_buffer_builder(context)?;
+
+        let mut raw_shifted = vec![];
+
+        for frame in frames {
+            // Unpack MIPI packed samples on the GPU, unless the buffer exceeds the storage buffer
+            // range of the device in which case Stage0 unpacks them on the host
+            let unpack = (frame.format.is_packed()
+                && frame.data.len().next_multiple_of(4)
+                    <= context.capabilities.max_storage_buffer_range as usize)
+                .then_some(Unpack {
+                    format: frame.format,
+                    buffer: frame.data,
+                    row_stride: frame.row_stride,
+                    extent,
+                });
+
+            // Shift Bayer color filter arrangement to match RGGB mosaic pattern
+            let stage0 = Stage0 {
+                color_filter_arrangement: frame.color_filter_arrangement,
+                format: frame.format,
+                buffer: frame.data,
+                row_stride: frame.row_stride,
+                extent,
+            };
+
+            let unpacked = unpack
+                .map(|unpack| {
+                    stage::record_stage(
+                        &unpack,
+                        context,
+                        &mut command_buffer_builder,
+                        None,
+                        work_groups,
+                    )
+                })
+                .transpose()?;
+
+            let mut output = stage::record_stage(
+                &stage0,
+                context,
+                &mut command_buffer_builder,
+                unpacked,
+                work_groups,
+            )?;
+
+            // Hot and dead pixel correction of every frame, ahead of the alignment whose L1 cost
+            // a hot pixel would bias and of the white balance statistics in which it would pass
+            // for the white patch
+            if params.defect_threshold > 0.0 || !params.bad_pixels.is_empty() {
+                let black_level =
+                    frame.black_level.iter().sum::<i32>() as f32 / frame.black_level.len() as f32;
+
+                let defect_correction = DefectCorrection {
+                    threshold: params.defect_threshold * (frame.white_level as f32 - black_level),
+                    bad_pixels: &params.bad_pixels,
+                    shift_vector: shift_vector(frame.color_filter_arrangement),
+                    extent,
+                };
+
+                output = stage::record_stage(
+                    &defect_correction,
+                    context,
+                    &mut command_buffer_builder,
+                    Some(output),
+                    work_groups,
+                )?;
+            }
+
+            raw_shifted.push(output.image_views[0].clone());
+        }
+
+        // Tile alignment and temporal merge of the burst
+        let merged = if frames.len() > 1 {
+            let black_level = shift_channels(image.black_level, image.color_filter_arrangement)
+                .map(|value| value as f32);
+            let range = image.white_level as f32
+                - image.black_level.iter().sum::<i32>() as f32 / image.black_level.len() as f32;
+
+            let noise_profile = shift_channels(
+                params.noise_profile.unwrap_or([DEFAULT_NOISE_PROFILE; 4]),
+                image.color_filter_arrangement,
+            );
+
+            merge::merge(
+                context,
+                &mut command_buffer_builder,
+                &raw_shifted,
+                extent,
+                NoiseModel {
+                    scale: noise_profile.map(|[scale, _]| scale * range),
+                    offset: noise_profile.map(|[_, offset]| offset * range * range),
+                    black_level,
+                },
+            )?
+        } else {
+            StageOutput {
+                image_views: raw_shifted,
+                buffers: vec![],
+            }
+        };
+
+        let mut stage_output = merged;
+
+        let color_spec = params.color_spec();
+
+        // White balance, as the camera neutral of the scene illuminant. Estimated ones are only
+        // known on the GPU, the host interpolates the calibration at the camera neutral.
+        let camera_neutral = params.neutral_point.map(|value| value as f64);
+        let neutral = match params.white_balance {
+            WhiteBalance::Camera
+            | WhiteBalance::GrayWorld
+            | WhiteBalance::WhitePatch
+            | WhiteBalance::GrayEdge => camera_neutral,
+            WhiteBalance::Gains(gains) => color::gains_to_neutral(gains),
+            WhiteBalance::Temperature { temperature, tint } => color_spec.xy_to_neutral(
+                color::Temperature {
+                    temperature: temperature as f64,
+                    tint: tint as f64,
+                }
+                .to_xy(),
+            ),
+        };
+
+        // Gains of the shifted mosaic, given ones may differ between the two greens
+        let color_gains = match params.white_balance {
+            WhiteBalance::Camera => params.color_gains,
+            WhiteBalance::Gains(gains) => gains,
+            _ => color::neutral_to_gains(neutral),
+        };
+
+        let black_level = shift_channels(image.black_level, image.color_filter_arrangement);
+        let white_level = image.white_level as f32;
+
+        // Gains and clip levels read by Stage1, the highlight reconstruction and the denoising.
+        // The estimation falls back to the camera gains on the GPU for frames without usable
+        // statistics.
+        let estimated = matches!(
+            params.white_balance,
+            WhiteBalance::GrayWorld | WhiteBalance::WhitePatch | WhiteBalance::GrayEdge
+        );
+        let white_balance = if estimated {
+            awb::record_white_balance(
+                context,
+                &mut command_buffer_builder,
+                stage_output.image_views[0].clone(),
+                black_level.map(|value| value as f32),
+                white_level,
+                params.white_balance,
+                color_gains,
+            )?
+        } else {
+            let clip_factor = awb::clip_factor(black_level.map(|value| value as f32), white_level);
+
+            awb::white_balance_buffer(
+                context,
+                color_gains,
+                array::from_fn(|index| color_gains[index] * clip_factor[index]),
+            )?
         };
 
         // Black level subtraction, white balancing and normalization
         let stage1 = Stage1 {
-            color_gains,
+            white_balance: white_balance.clone(),
             black_level,
-            white_level,
+            white_level: image.white_level,
             extent,
         };
 
-        // Demosaicing
-        let stage2 = Stage2 { extent };
+        // Raw denoising, before lens shading correction amplifies the noise towards the corners
+        let denoise = params
+            .noise_profile
+            .filter(|_| params.denoise_strength > 0.0)
+            .map(|noise_profile| {
+                let noise_profile = shift_channels(noise_profile, image.color_filter_arrangement);
+
+                Denoise {
+                    scale: noise_profile.map(|[scale, _]| scale),
+                    offset: noise_profile.map(|[_, offset]| offset),
+                    strength: params.denoise_strength,
+                    white_balance: white_balance.clone(),
+                    extent,
+                }
+            });
+
+        let (shading_gains, map_size) = shading_gains(
+            context,
+            params.lens_shading_map.as_ref(),
+            image.color_filter_arrangement,
+        )?;
+
+        // Lens shading correction, vignetting and color shading towards the corners
+        let lens_shading = params.lens_shading_map.as_ref().map(|_| LensShading {
+            shading_gains: shading_gains.clone(),
+            map_size,
+            extent,
+        });
+
+        // Highlight reconstruction, after lens shading correction so that clipped highlights
+        // come out neutral instead of taking on the color shading of the corners
+        let highlight_reconstruction = HighlightReconstruction {
+            mode: params.highlight_mode,
+            white_balance: white_balance.clone(),
+            shading_gains,
+            map_size,
+            extent,
+        };
 
-        // Color correction (sensor color space to CIE XYZ and then to linear sRGB)
-        let stage3 = Stage3 {
-            // forward_matrix_1,
-            // forward_matrix_2,
-            color_correction_transform,
-            // neutral_point,
+        // Demosaicing
+        let stage2 = Stage2 {
+            algorithm: params.demosaic_algorithm,
+            format: rgb_format(params.output_format),
+            extent,
         };
 
-        // Gamma correction
-        let stage4 = Stage4 {};
+        let primaries = primaries(params.color_space);
+        let linear = params.color_space == ColorSpace::Linear;
+
+        // Color correction (sensor color space to CIE XYZ and then to the linear output space)
+        let stage3 = {
+            let (camera_to_xyz, temperature) = color_spec.camera_to_xyz_d50(neutral);
+
+            if estimated {
+                info!(
+                    "{:?} white balance, calibration at {:.0}K, tint {:.1}",
+                    params.white_balance, temperature.temperature, temperature.tint
+                );
+            } else {
+                info!(
+                    "Scene illuminant {:.0}K, tint {:.1}",
+                    temperature.temperature, temperature.tint
+                );
+            }
+
+            // Stage1 has already multiplied the samples by the color gains, undo them before
+            // applying the white balance derived from the neutral point. With an estimated white
+            // balance they cancel out the camera neutral, leaving the forward matrix for the
+            // mosaic white balanced on the GPU.
+            let gains = [
+                color_gains[0] as f64,
+                ((color_gains[1] + color_gains[2]) * 0.5) as f64,
+                color_gains[3] as f64,
+            ];
+            let camera_to_xyz = color::mul(
+                &camera_to_xyz,
+                &color::diagonal(gains.map(|value| 1.0 / value)),
+            );
+            let mut camera_to_rgb = color::mul(&color::xyz_d50_to_rgb(primaries), &camera_to_xyz);
+
+            // Linear output skips the tone mapping, the exposure bias is applied here instead
+            if linear {
+                let exposure = (params.exposure_bias as f64).exp2();
+                camera_to_rgb = color::mul(&color::diagonal([exposure; 3]), &camera_to_rgb);
+            }
 
-        // Quantization
-        let stage5 = Stage5 { extent };
+            Stage3 {
+                camera_to_rgb: color::matrix_to_row_major(&camera_to_rgb),
+            }
+        };
 
-        let stages: Vec<&dyn StageInPipeline> =
-            vec![&stage0, &stage1, &stage2, &stage3, &stage4, &stage5];
+        let luminance = color::luminance_coefficients(primaries).map(|value| value as f32);
 
-        let mut command_buffer_builder = AutoCommandBufferBuilder::primary(
-            context.command_buffer_allocator.clone(),
-            context.queue.queue_family_index(),
-            CommandBufferUsage::OneTimeSubmit,
-        )
-        .unwrap();
+        // Tone mapping
+        let tone_map = ToneMap {
+            operator: &params.tone_map_operator,
+            exposure_bias: params.exposure_bias,
+            luminance,
+            headroom: headroom(params.color_space),
+        };
 
-        let work_groups = {
-            let w = extent[0];
-            let h = extent[1];
-            // Rounding up
-            [(w + 7) / 8, (h + 7) / 8, 1]
+        // Gamma correction, encodes with the transfer function of the output color space
+        let stage4 = Stage4 {
+            color_space: params.color_space,
         };
 
-        let mut stage_output: Option<StageOutput> = None;
+        // Quantization, packs the output format straight into the readback buffer
+        let stage5 = Stage5::new(context, params.output_format, extent)?;
+
+        let mut stages: Vec<&dyn StageInPipeline> = vec![&stage1];
+        if let Some(denoise) = &denoise {
+            stages.push(denoise);
+        }
+        if let Some(lens_shading) = &lens_shading {
+            stages.push(lens_shading);
+        }
+        stages.extend([
+            &highlight_reconstruction as &dyn StageInPipeline,
+            &stage2,
+            &stage3,
+        ]);
+
+        for stage in stages {
+            stage_output = stage::record_stage(
+                stage,
+                context,
+                &mut command_buffer_builder,
+                Some(stage_output),
+                work_groups,
+            )?;
+        }
+
+        // Local tone mapping, records the pyramids of the exposure fusion on its own
+        if params.local_tone_map_strength > 0.0 && !linear {
+            stage_output = fusion::fuse(
+                context,
+                &mut command_buffer_builder,
+                stage_output,
+                extent,
+                luminance,
+                params.synthetic_exposures,
+                params.local_tone_map_strength,
+            )?;
+        }
+
+        let mut stages: Vec<&dyn StageInPipeline> = vec![];
+        if !linear {
+            stages.push(&tone_map);
+        }
+        stages.push(&stage4);
 
         for stage in stages {
-            let resources = stage.create_stage_resources(&context, stage_output);
-            stage.bind_stage_pipeline_and_dispatch(
+            stage_output = stage::record_stage(
+                stage,
+                context,
                 &mut command_buffer_builder,
-                &resources,
+                Some(stage_output),
                 work_groups,
-            );
-            stage_output = Some(StageOutput {
-                image_views: resources.image_views,
-                buffers: resources.buffers,
-                commands: resources.commands,
-            })
+            )?;
         }
 
-        let command_buffer = command_buffer_builder.build().unwrap();
+        // Dispatched over the words of the output rather than the pixels
+        stage::record_stage(
+            &stage5,
+            context,
+            &mut command_buffer_builder,
+            Some(stage_output),
+            stage5.work_groups(),
+        )?;
 
-        sync::now(context.device.clone())
-            .then_execute(context.queue.clone(), command_buffer)
-            .unwrap()
-            .then_signal_fence_and_flush()
-            .unwrap()
-            .wait(None)
-            .unwrap();
+        // Upload, every dispatch and the readback are submitted together, barriers between them
+        // are inserted by the command buffer builder
+        let command_buffer = command_buffer_builder.build()?;
 
-        // Copy quantized image to buffer
-        if let Some(stage_output) = stage_output {
-            stage_output.commands[0]
-                .clone()
-                .execute(context.queue.clone())
-                .unwrap()
-                .then_signal_fence_and_flush()
-                .unwrap()
-                .wait(None)
-                .unwrap();
+        let future = sync::now(context.device.clone())
+            .then_execute(context.queue.clone(), command_buffer)?
+            .then_signal_fence_and_flush()?;
 
-            // Subbufer containts metadata of the GPU buffer
-            self.output = stage_output.buffers.get(0).cloned()
+        Ok((future, stage5.output()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shifts_channels_to_rggb() {
+        let cases = [
+            (0, ['R', 'G', 'G', 'B']),
+            (1, ['G', 'R', 'B', 'G']),
+            (2, ['G', 'B', 'R', 'G']),
+            (3, ['B', 'G', 'G', 'R']),
+        ];
+
+        for (color_filter_arrangement, channels) in cases {
+            assert_eq!(
+                shift_channels(channels, color_filter_arrangement),
+                ['R', 'G', 'G', 'B'],
+                "arrangement {color_filter_arrangement}"
+            );
         }
     }
 
-    pub fn get_buffer_output(&self) -> Option<Subbuffer<[u8]>> {
-        self.output.clone()
+    #[test]
+    fn builds_defect_mask() {
+        // 6x6 mosaic, 36 bits over 2 words
+        let extent = [6, 6, 1];
+        let bad_pixels = [[0, 0], [1, 0], [3, 2], [0, 1], [5, 5], [6, 3], [2, 6]];
+
+        // GRBG, sensor (x, y) lands on (x - 1, y), the first column and
+        // the row past the bottom edge are dropped
+        assert_eq!(
+            defect_mask(&bad_pixels, [1, 0], extent),
+            // (0, 0), (2, 2) = bit 14, (4, 5) = bit 34, (5, 3) = bit 23
+            vec![1 << 0 | 1 << 14 | 1 << 23, 1 << 2],
+        );
+
+        // GBRG, sensor (x, y) lands on (x, y - 1), the first row and the
+        // column past the right edge are dropped
+        assert_eq!(
+            defect_mask(&bad_pixels, [0, 1], extent),
+            // (3, 1) = bit 9, (0, 0), (5, 4) = bit 29, (2, 5) = bit 32
+            vec![1 << 0 | 1 << 9 | 1 << 29, 1 << 0],
+        );
     }
 }
@@ -0,0 +1,418 @@
+use std::sync::Arc;
+
+use vulkano::{
+    buffer::BufferContents,
+    command_buffer::{AutoCommandBufferBuilder, ClearColorImageInfo, PrimaryAutoCommandBuffer},
+    descriptor_set::{DescriptorSet, WriteDescriptorSet},
+    format::{ClearColorValue, Format},
+    image::{ImageUsage, view::ImageView},
+    pipeline::{Pipeline, PipelineBindPoint},
+};
+
+use crate::{
+    error::RawProcessorError,
+    pipeline::{
+        context,
+        shaders::Shader,
+        stage::{self, StageInPipeline, StageOutput, StageResources},
+    },
+};
+
+// Pyramid levels at most, fewer when the coarsest level would be smaller than this many pixels
+const MAX_LEVELS: usize = 8;
+const MIN_LEVEL_SIZE: u32 = 16;
+
+// Synthetic exposures and weights of the finest level
+struct SyntheticExposures {
+    luminance: [f32; 4],
+    exposures: u32,
+    strength: f32,
+
+    extent: [u32; 3],
+}
+
+// Gaussian pyramid level of the exposures or the weights
+struct FusionDownsample {
+    extent: [u32; 3],
+}
+
+// Fused Laplacian level added to the collapsed coarser levels
+struct FusionCollapse {
+    extent: [u32; 3],
+}
+
+// Applies the fused luminance to the RGB image
+struct FusionApply {
+    luminance: [f32; 4],
+}
+
+// Storage image of a pyramid level
+fn level_image_view(
+    context: &context::Context,
+    format: Format,
+    extent: [u32; 3],
+) -> Result<Arc<ImageView>, RawProcessorError> {
+    let image = context.pool.image(format, extent, ImageUsage::STORAGE)?;
+
+    Ok(ImageView::new_default(image)?)
+}
+
+// Single zero texel standing in for the level above the coarsest one
+fn zero_image_view(
+    context: &context::Context,
+    command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    format: Format,
+) -> Result<Arc<ImageView>, RawProcessorError> {
+    let image = context.pool.image(
+        format,
+        [1, 1, 1],
+        ImageUsage::STORAGE | ImageUsage::TRANSFER_DST,
+    )?;
+
+    command_buffer_builder.clear_color_image(ClearColorImageInfo {
+        clear_value: ClearColorValue::Float([0.0; 4]),
+        ..ClearColorImageInfo::image(image.clone())
+    })?;
+
+    Ok(ImageView::new_default(image)?)
+}
+
+// Binds the pipeline and descriptor set of a stage without push constants and dispatches it
+fn dispatch(
+    command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    resources: &StageResources,
+    work_groups: [u32; 3],
+) -> Result<(), RawProcessorError> {
+    command_buffer_builder
+        .bind_pipeline_compute(resources.compute_pipeline.clone())?
+        .bind_descriptor_sets(
+            PipelineBindPoint::Compute,
+            resources.compute_pipeline.layout().clone(),
+            0,
+            resources.descriptor_set.clone(),
+        )?;
+
+    unsafe {
+        command_buffer_builder.dispatch(work_groups)?;
+    }
+
+    Ok(())
+}
+
+impl StageInPipeline for SyntheticExposures {
+    fn create_stage_resources(
+        &self,
+        context: &context::Context,
+        input: Option<StageOutput>,
+    ) -> Result<StageResources, RawProcessorError> {
+        let exposures_image_view =
+            level_image_view(context, Format::R16G16B16A16_SFLOAT, self.extent)?;
+        let weights_image_view =
+            level_image_view(context, Format::R16G16B16A16_SFLOAT, self.extent)?;
+
+        let compute_pipeline = context.pipelines.get(
+            Shader::FusionExposures.for_format(input.as_ref().unwrap().image_views[0].format()),
+        );
+
+        let layout = compute_pipeline.layout().set_layouts().get(0).unwrap();
+        let descriptor_set = DescriptorSet::new(
+            context.descriptor_set_allocator.clone(),
+            layout.clone(),
+            [
+                WriteDescriptorSet::image_view(
+                    0,
+                    input.unwrap().image_views.get(0).unwrap().clone(),
+                ),
+                WriteDescriptorSet::image_view(1, exposures_image_view.clone()),
+                WriteDescriptorSet::image_view(2, weights_image_view.clone()),
+            ],
+            [],
+        )?;
+
+        Ok(StageResources {
+            compute_pipeline,
+            descriptor_set,
+            image_views: vec![exposures_image_view, weights_image_view],
+            buffers: vec![],
+        })
+    }
+
+    fn bind_stage_pipeline_and_dispatch(
+        &self,
+        command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        resources: &StageResources,
+        work_groups: [u32; 3],
+    ) -> Result<(), RawProcessorError> {
+        #[derive(BufferContents)]
+        #[repr(C)]
+        struct Constants {
+            luminance: [f32; 4],
+            exposure_count: u32,
+            strength: f32,
+        }
+
+        let constants = Constants {
+            luminance: self.luminance,
+            exposure_count: self.exposures,
+            strength: self.strength,
+        };
+
+        command_buffer_builder.push_constants(
+            resources.compute_pipeline.layout().clone(),
+            0,
+            constants,
+        )?;
+
+        dispatch(command_buffer_builder, resources, work_groups)
+    }
+}
+
+impl StageInPipeline for FusionDownsample {
+    fn create_stage_resources(
+        &self,
+        context: &context::Context,
+        input: Option<StageOutput>,
+    ) -> Result<StageResources, RawProcessorError> {
+        let level_image_view = level_image_view(context, Format::R16G16B16A16_SFLOAT, self.extent)?;
+
+        let compute_pipeline = context.pipelines.get(Shader::FusionDownsample);
+
+        let layout = compute_pipeline.layout().set_layouts().get(0).unwrap();
+        let descriptor_set = DescriptorSet::new(
+            context.descriptor_set_allocator.clone(),
+            layout.clone(),
+            [
+                WriteDescriptorSet::image_view(
+                    0,
+                    input.unwrap().image_views.get(0).unwrap().clone(),
+                ),
+                WriteDescriptorSet::image_view(1, level_image_view.clone()),
+            ],
+            [],
+        )?;
+
+        Ok(StageResources {
+            compute_pipeline,
+            descriptor_set,
+            image_views: vec![level_image_view],
+            buffers: vec![],
+        })
+    }
+
+    fn bind_stage_pipeline_and_dispatch(
+        &self,
+        command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        resources: &StageResources,
+        work_groups: [u32; 3],
+    ) -> Result<(), RawProcessorError> {
+        dispatch(command_buffer_builder, resources, work_groups)
+    }
+}
+
+impl StageInPipeline for FusionCollapse {
+    // Input holds the exposures of the level and of the coarser level, the weights of the level
+    // and the collapsed coarser levels
+    fn create_stage_resources(
+        &self,
+        context: &context::Context,
+        input: Option<StageOutput>,
+    ) -> Result<StageResources, RawProcessorError> {
+        let input = input.unwrap();
+
+        let fused_image_view = level_image_view(context, Format::R16_SFLOAT, self.extent)?;
+
+        let compute_pipeline = context.pipelines.get(Shader::FusionCollapse);
+
+        let layout = compute_pipeline.layout().set_layouts().get(0).unwrap();
+        let descriptor_set = DescriptorSet::new(
+            context.descriptor_set_allocator.clone(),
+            layout.clone(),
+            [
+                WriteDescriptorSet::image_view(0, input.image_views[0].clone()),
+                WriteDescriptorSet::image_view(1, input.image_views[1].clone()),
+                WriteDescriptorSet::image_view(2, input.image_views[2].clone()),
+                WriteDescriptorSet::image_view(3, input.image_views[3].clone()),
+                WriteDescriptorSet::image_view(4, fused_image_view.clone()),
+            ],
+            [],
+        )?;
+
+        Ok(StageResources {
+            compute_pipeline,
+            descriptor_set,
+            image_views: vec![fused_image_view],
+            buffers: vec![],
+        })
+    }
+
+    fn bind_stage_pipeline_and_dispatch(
+        &self,
+        command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        resources: &StageResources,
+        work_groups: [u32; 3],
+    ) -> Result<(), RawProcessorError> {
+        dispatch(command_buffer_builder, resources, work_groups)
+    }
+}
+
+impl StageInPipeline for FusionApply {
+    // Input holds the RGB image and the fused luminance, the RGB image is modified in place
+    fn create_stage_resources(
+        &self,
+        context: &context::Context,
+        input: Option<StageOutput>,
+    ) -> Result<StageResources, RawProcessorError> {
+        let input = input.unwrap();
+
+        let compute_pipeline = context
+            .pipelines
+            .get(Shader::FusionApply.for_format(input.image_views[0].format()));
+
+        let layout = compute_pipeline.layout().set_layouts().get(0).unwrap();
+        let descriptor_set = DescriptorSet::new(
+            context.descriptor_set_allocator.clone(),
+            layout.clone(),
+            [
+                WriteDescriptorSet::image_view(0, input.image_views[0].clone()),
+                WriteDescriptorSet::image_view(1, input.image_views[1].clone()),
+            ],
+            [],
+        )?;
+
+        Ok(StageResources {
+            compute_pipeline,
+            descriptor_set,
+            image_views: vec![input.image_views[0].clone()],
+            buffers: vec![],
+        })
+    }
+
+    fn bind_stage_pipeline_and_dispatch(
+        &self,
+        command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        resources: &StageResources,
+        work_groups: [u32; 3],
+    ) -> Result<(), RawProcessorError> {
+        #[derive(BufferContents)]
+        #[repr(C)]
+        struct Constants {
+            luminance: [f32; 4],
+        }
+
+        let constants = Constants {
+            luminance: self.luminance,
+        };
+
+        command_buffer_builder.push_constants(
+            resources.compute_pipeline.layout().clone(),
+            0,
+            constants,
+        )?;
+
+        dispatch(command_buffer_builder, resources, work_groups)
+    }
+}
+
+// Local tone mapping by exposure fusion of the linear RGB image of Stage3, luminance holds the
+// coefficients of its primaries
+pub fn fuse(
+    context: &context::Context,
+    command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    input: StageOutput,
+    extent: [u32; 3],
+    luminance: [f32; 3],
+    exposures: u32,
+    strength: f32,
+) -> Result<StageOutput, RawProcessorError> {
+    let luminance = [
+        luminance[0],
+        luminance[1],
+        luminance[2],
+        0.0, /* padding */
+    ];
+
+    let rgb = input.image_views[0].clone();
+
+    let output = stage::record_stage(
+        &SyntheticExposures {
+            luminance,
+            exposures,
+            strength,
+            extent,
+        },
+        context,
+        command_buffer_builder,
+        Some(input),
+        stage::work_groups(extent),
+    )?;
+
+    let mut extents = vec![extent];
+    let mut exposure_levels = vec![output.image_views[0].clone()];
+    let mut weight_levels = vec![output.image_views[1].clone()];
+
+    // Gaussian pyramids of the exposures and of the weights
+    while extents.len() < MAX_LEVELS {
+        let [width, height, _] = extents[extents.len() - 1];
+        if width.min(height) < MIN_LEVEL_SIZE * 2 {
+            break;
+        }
+
+        let extent = [width.div_ceil(2), height.div_ceil(2), 1];
+
+        for levels in [&mut exposure_levels, &mut weight_levels] {
+            let output = stage::record_stage(
+                &FusionDownsample { extent },
+                context,
+                command_buffer_builder,
+                Some(StageOutput {
+                    image_views: vec![levels[levels.len() - 1].clone()],
+                    buffers: vec![],
+                }),
+                stage::work_groups(extent),
+            )?;
+
+            levels.push(output.image_views[0].clone());
+        }
+
+        extents.push(extent);
+    }
+
+    // Collapse from the coarsest level, building the Laplacian levels on the fly
+    let mut exposures_coarser =
+        zero_image_view(context, command_buffer_builder, Format::R16G16B16A16_SFLOAT)?;
+    let mut fused = zero_image_view(context, command_buffer_builder, Format::R16_SFLOAT)?;
+
+    for level in (0..extents.len()).rev() {
+        let output = stage::record_stage(
+            &FusionCollapse {
+                extent: extents[level],
+            },
+            context,
+            command_buffer_builder,
+            Some(StageOutput {
+                image_views: vec![
+                    exposure_levels[level].clone(),
+                    exposures_coarser,
+                    weight_levels[level].clone(),
+                    fused,
+                ],
+                buffers: vec![],
+            }),
+            stage::work_groups(extents[level]),
+        )?;
+
+        exposures_coarser = exposure_levels[level].clone();
+        fused = output.image_views[0].clone();
+    }
+
+    stage::record_stage(
+        &FusionApply { luminance },
+        context,
+        command_buffer_builder,
+        Some(StageOutput {
+            image_views: vec![rgb, fused],
+            buffers: vec![],
+        }),
+        stage::work_groups(extent),
+    )
+}
@@ -0,0 +1,268 @@
+use std::sync::Arc;
+
+use vulkano::{
+    buffer::BufferContents,
+    command_buffer::{AutoCommandBufferBuilder, ClearColorImageInfo, PrimaryAutoCommandBuffer},
+    descriptor_set::{DescriptorSet, WriteDescriptorSet},
+    format::{ClearColorValue, Format},
+    image::{ImageUsage, view::ImageView},
+    pipeline::{Pipeline, PipelineBindPoint},
+};
+
+use crate::{
+    error::RawProcessorError,
+    pipeline::{
+        align, context,
+        shaders::Shader,
+        stage::{self, StageInPipeline, StageOutput, StageResources},
+    },
+};
+
+// Noise of each channel of the shifted mosaic in digital numbers, the variance of a sample x
+// is scale * (x - black_level) + offset
+#[derive(Clone, Copy, Debug)]
+pub struct NoiseModel {
+    pub scale: [f32; 4],
+    pub offset: [f32; 4],
+    pub black_level: [f32; 4],
+}
+
+// Adds an aligned alternate frame to the accumulator
+struct Merge {
+    noise_model: NoiseModel,
+}
+
+// Averages the reference frame and the accumulator back into a mosaic
+struct Interleave {
+    frame_count: u32,
+
+    extent: [u32; 3],
+}
+
+impl StageInPipeline for Merge {
+    // Input holds the reference and alternate mosaics, the alignment and the accumulator
+    fn create_stage_resources(
+        &self,
+        context: &context::Context,
+        input: Option<StageOutput>,
+    ) -> Result<StageResources, RawProcessorError> {
+        let input = input.unwrap();
+
+        let compute_pipeline = context.pipelines.get(Shader::Merge);
+
+        let layout = compute_pipeline.layout().set_layouts().get(0).unwrap();
+        let descriptor_set = DescriptorSet::new(
+            context.descriptor_set_allocator.clone(),
+            layout.clone(),
+            [
+                WriteDescriptorSet::image_view(0, input.image_views[0].clone()),
+                WriteDescriptorSet::image_view(1, input.image_views[1].clone()),
+                WriteDescriptorSet::image_view(2, input.image_views[2].clone()),
+                WriteDescriptorSet::image_view(3, input.image_views[3].clone()),
+            ],
+            [],
+        )?;
+
+        Ok(StageResources {
+            compute_pipeline,
+            descriptor_set,
+            image_views: vec![input.image_views[3].clone()],
+            buffers: vec![],
+        })
+    }
+
+    // One work group per tile, for every other tile in both directions
+    fn bind_stage_pipeline_and_dispatch(
+        &self,
+        command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        resources: &StageResources,
+        work_groups: [u32; 3],
+    ) -> Result<(), RawProcessorError> {
+        #[derive(BufferContents)]
+        #[repr(C)]
+        struct Constants {
+            noise_scale: [f32; 4],
+            noise_offset: [f32; 4],
+            black_level: [f32; 4],
+            parity: [i32; 2],
+        }
+
+        command_buffer_builder
+            .bind_pipeline_compute(resources.compute_pipeline.clone())?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                resources.compute_pipeline.layout().clone(),
+                0,
+                resources.descriptor_set.clone(),
+            )?;
+
+        // Overlapping tiles accumulate into the same samples, so they go into separate
+        // dispatches
+        for parity in [[0, 0], [1, 0], [0, 1], [1, 1]] {
+            command_buffer_builder.push_constants(
+                resources.compute_pipeline.layout().clone(),
+                0,
+                Constants {
+                    noise_scale: self.noise_model.scale,
+                    noise_offset: self.noise_model.offset,
+                    black_level: self.noise_model.black_level,
+                    parity,
+                },
+            )?;
+
+            unsafe {
+                command_buffer_builder.dispatch(work_groups)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl StageInPipeline for Interleave {
+    // Input holds the reference mosaic and the accumulator
+    fn create_stage_resources(
+        &self,
+        context: &context::Context,
+        input: Option<StageOutput>,
+    ) -> Result<StageResources, RawProcessorError> {
+        let input = input.unwrap();
+
+        let (_, raw_merged_image_view) = {
+            let image = context
+                .pool
+                .image(Format::R16_UINT, self.extent, ImageUsage::STORAGE)?;
+
+            let view = ImageView::new_default(image.clone())?;
+
+            (image, view)
+        };
+
+        let compute_pipeline = context.pipelines.get(Shader::Interleave);
+
+        let layout = compute_pipeline.layout().set_layouts().get(0).unwrap();
+        let descriptor_set = DescriptorSet::new(
+            context.descriptor_set_allocator.clone(),
+            layout.clone(),
+            [
+                WriteDescriptorSet::image_view(0, input.image_views[0].clone()),
+                WriteDescriptorSet::image_view(1, input.image_views[1].clone()),
+                WriteDescriptorSet::image_view(2, raw_merged_image_view.clone()),
+            ],
+            [],
+        )?;
+
+        Ok(StageResources {
+            compute_pipeline,
+            descriptor_set,
+            image_views: vec![raw_merged_image_view],
+            buffers: vec![],
+        })
+    }
+
+    fn bind_stage_pipeline_and_dispatch(
+        &self,
+        command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        resources: &StageResources,
+        work_groups: [u32; 3],
+    ) -> Result<(), RawProcessorError> {
+        #[derive(BufferContents)]
+        #[repr(C)]
+        struct Constants {
+            frame_count: u32,
+        }
+
+        let constants = Constants {
+            frame_count: self.frame_count,
+        };
+
+        command_buffer_builder
+            .bind_pipeline_compute(resources.compute_pipeline.clone())?
+            .push_constants(resources.compute_pipeline.layout().clone(), 0, constants)?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                resources.compute_pipeline.layout().clone(),
+                0,
+                resources.descriptor_set.clone(),
+            )?;
+
+        unsafe {
+            command_buffer_builder.dispatch(work_groups)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Aligns every alternate frame to the first one and merges them into a single shifted mosaic
+// of the same extent
+pub fn merge(
+    context: &context::Context,
+    command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    raw_shifted: &[Arc<ImageView>],
+    extent: [u32; 3],
+    noise_model: NoiseModel,
+) -> Result<StageOutput, RawProcessorError> {
+    let reference = &raw_shifted[0];
+    let reference_pyramid =
+        align::build_pyramid(context, command_buffer_builder, reference.clone(), extent)?;
+
+    let quads = [extent[0] / 2, extent[1] / 2, 1];
+    let tiles = align::tile_count(quads);
+
+    let accumulator = {
+        let image = context.pool.image(
+            Format::R32G32B32A32_SFLOAT,
+            quads,
+            ImageUsage::STORAGE | ImageUsage::TRANSFER_DST,
+        )?;
+
+        command_buffer_builder.clear_color_image(ClearColorImageInfo {
+            clear_value: ClearColorValue::Float([0.0; 4]),
+            ..ClearColorImageInfo::image(image.clone())
+        })?;
+
+        ImageView::new_default(image)?
+    };
+
+    for alternate in &raw_shifted[1..] {
+        let pyramid =
+            align::build_pyramid(context, command_buffer_builder, alternate.clone(), extent)?;
+        let alignment = align::align(
+            context,
+            command_buffer_builder,
+            &reference_pyramid,
+            &pyramid,
+        )?;
+
+        stage::record_stage(
+            &Merge { noise_model },
+            context,
+            command_buffer_builder,
+            Some(StageOutput {
+                image_views: vec![
+                    reference.clone(),
+                    alternate.clone(),
+                    alignment,
+                    accumulator.clone(),
+                ],
+                buffers: vec![],
+            }),
+            [tiles[0].div_ceil(2), tiles[1].div_ceil(2), 1],
+        )?;
+    }
+
+    stage::record_stage(
+        &Interleave {
+            frame_count: raw_shifted.len() as u32,
+            extent,
+        },
+        context,
+        command_buffer_builder,
+        Some(StageOutput {
+            image_views: vec![reference.clone(), accumulator],
+            buffers: vec![],
+        }),
+        stage::work_groups(extent),
+    )
+}
@@ -1,6 +1,17 @@
+mod align;
+mod awb;
+mod cache;
 mod context;
+mod device;
 mod finish;
+mod fusion;
+mod merge;
+mod pool;
+mod registry;
+mod shaders;
 mod stage;
+mod unpack;
 
-pub use context::Context;
-pub use finish::Finish;
+pub use context::{Context, ContextCreateInfo};
+pub use device::{DeviceCapabilities, DeviceSelection};
+pub use finish::{Finish, Submission};
@@ -0,0 +1,177 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use log::info;
+use vulkano::{
+    DeviceSize,
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    format::Format,
+    image::{Image, ImageCreateInfo, ImageUsage},
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+};
+
+use crate::error::RawProcessorError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct ImageKey {
+    format: Format,
+    extent: [u32; 3],
+    usage: ImageUsage,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct BufferKey {
+    size: DeviceSize,
+    usage: BufferUsage,
+    readback: bool,
+}
+
+// Images and host visible staging buffers recycled across frames of the same size.
+//
+// The pool keeps a reference to every resource it hands out. A resource becomes free again
+// once the pool holds the only reference, that is when every view, descriptor set, command
+// buffer and output that used it has been dropped.
+pub struct ResourcePool {
+    memory_allocator: Arc<StandardMemoryAllocator>,
+
+    images: Mutex<HashMap<ImageKey, Vec<Arc<Image>>>>,
+    buffers: Mutex<HashMap<BufferKey, Vec<Subbuffer<[u8]>>>>,
+}
+
+impl ResourcePool {
+    pub fn new(memory_allocator: Arc<StandardMemoryAllocator>) -> ResourcePool {
+        ResourcePool {
+            memory_allocator,
+            images: Mutex::new(HashMap::new()),
+            buffers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn image(
+        &self,
+        format: Format,
+        extent: [u32; 3],
+        usage: ImageUsage,
+    ) -> Result<Arc<Image>, RawProcessorError> {
+        let key = ImageKey {
+            format,
+            extent,
+            usage,
+        };
+
+        let mut images = self.images.lock().unwrap();
+        let entries = images.entry(key).or_default();
+
+        if let Some(image) = entries.iter().find(|image| Arc::strong_count(image) == 1) {
+            return Ok(image.clone());
+        }
+
+        let image = Image::new(
+            self.memory_allocator.clone(),
+            ImageCreateInfo {
+                format,
+                extent,
+                usage,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )?;
+
+        entries.push(image.clone());
+
+        Ok(image)
+    }
+
+    // Buffers written sequentially by the host, such as staging buffers and shader inputs
+    pub fn buffer(
+        &self,
+        size: DeviceSize,
+        usage: BufferUsage,
+    ) -> Result<Subbuffer<[u8]>, RawProcessorError> {
+        self.host_buffer(size, usage, false)
+    }
+
+    // Buffers written by the GPU and read back by the host, in cached memory
+    pub fn readback_buffer(
+        &self,
+        size: DeviceSize,
+        usage: BufferUsage,
+    ) -> Result<Subbuffer<[u8]>, RawProcessorError> {
+        self.host_buffer(size, usage, true)
+    }
+
+    fn host_buffer(
+        &self,
+        size: DeviceSize,
+        usage: BufferUsage,
+        readback: bool,
+    ) -> Result<Subbuffer<[u8]>, RawProcessorError> {
+        let key = BufferKey {
+            size,
+            usage,
+            readback,
+        };
+
+        let mut buffers = self.buffers.lock().unwrap();
+        let entries = buffers.entry(key).or_default();
+
+        if let Some(buffer) = entries
+            .iter()
+            .find(|buffer| Arc::strong_count(buffer.buffer()) == 1)
+        {
+            return Ok(buffer.clone());
+        }
+
+        let host_access = if readback {
+            MemoryTypeFilter::HOST_RANDOM_ACCESS
+        } else {
+            MemoryTypeFilter::HOST_SEQUENTIAL_WRITE
+        };
+
+        let buffer = Buffer::new_slice::<u8>(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST | host_access,
+                ..Default::default()
+            },
+            size,
+        )?;
+
+        entries.push(buffer.clone());
+
+        Ok(buffer)
+    }
+
+    // Releases every pooled resource that is not currently in use
+    pub fn trim(&self) {
+        let mut released = 0;
+
+        {
+            let mut images = self.images.lock().unwrap();
+            for entries in images.values_mut() {
+                let before = entries.len();
+                entries.retain(|image| Arc::strong_count(image) > 1);
+                released += before - entries.len();
+            }
+            images.retain(|_, entries| !entries.is_empty());
+        }
+
+        {
+            let mut buffers = self.buffers.lock().unwrap();
+            for entries in buffers.values_mut() {
+                let before = entries.len();
+                entries.retain(|buffer| Arc::strong_count(buffer.buffer()) > 1);
+                released += before - entries.len();
+            }
+            buffers.retain(|_, entries| !entries.is_empty());
+        }
+
+        info!("Released {released} pooled resources");
+    }
+}
@@ -0,0 +1,65 @@
+use std::{collections::HashMap, sync::Arc};
+
+use log::info;
+use vulkano::{
+    device::Device,
+    pipeline::{
+        ComputePipeline, PipelineLayout, PipelineShaderStageCreateInfo, cache::PipelineCache,
+        compute::ComputePipelineCreateInfo, layout::PipelineDescriptorSetLayoutCreateInfo,
+    },
+};
+
+use crate::{error::RawProcessorError, pipeline::shaders::Shader};
+
+// Compute pipelines are built once when the context is created and shared by every frame
+pub struct PipelineRegistry {
+    pipelines: HashMap<Shader, Arc<ComputePipeline>>,
+}
+
+impl PipelineRegistry {
+    pub fn new(
+        device: Arc<Device>,
+        cache: Option<Arc<PipelineCache>>,
+    ) -> Result<PipelineRegistry, RawProcessorError> {
+        let mut pipelines = HashMap::new();
+
+        for shader in Shader::ALL {
+            pipelines.insert(
+                shader,
+                create_compute_pipeline(device.clone(), cache.clone(), shader)?,
+            );
+        }
+
+        info!("Created {} compute pipelines", pipelines.len());
+
+        Ok(PipelineRegistry { pipelines })
+    }
+
+    pub fn get(&self, shader: Shader) -> Arc<ComputePipeline> {
+        self.pipelines[&shader].clone()
+    }
+}
+
+fn create_compute_pipeline(
+    device: Arc<Device>,
+    cache: Option<Arc<PipelineCache>>,
+    shader: Shader,
+) -> Result<Arc<ComputePipeline>, RawProcessorError> {
+    let compute_shader = shader.load(device.clone())?;
+    let stage = PipelineShaderStageCreateInfo::new(
+        compute_shader
+            .entry_point("main")
+            .ok_or(RawProcessorError::MissingEntryPoint)?,
+    );
+    let layout = PipelineLayout::new(
+        device.clone(),
+        PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+            .into_pipeline_layout_create_info(device.clone())?,
+    )?;
+
+    Ok(ComputePipeline::new(
+        device,
+        cache,
+        ComputePipelineCreateInfo::stage_layout(stage, layout),
+    )?)
+}
@@ -0,0 +1,355 @@
+use std::sync::Arc;
+
+use vulkano::{Validated, VulkanError, device::Device, format::Format, shader::ShaderModule};
+
+mod unpack {
+    vulkano_shaders::shader! {
+        bytes: "shaders/unpack.spv"
+    }
+}
+
+mod shiftbayer {
+    vulkano_shaders::shader! {
+        bytes: "shaders/shiftbayer.spv"
+    }
+}
+
+mod grayscale {
+    vulkano_shaders::shader! {
+        bytes: "shaders/grayscale.spv"
+    }
+}
+
+mod downsample {
+    vulkano_shaders::shader! {
+        bytes: "shaders/downsample.spv"
+    }
+}
+
+mod align {
+    vulkano_shaders::shader! {
+        bytes: "shaders/align.spv"
+    }
+}
+
+mod merge {
+    vulkano_shaders::shader! {
+        bytes: "shaders/merge.spv"
+    }
+}
+
+mod interleave {
+    vulkano_shaders::shader! {
+        bytes: "shaders/interleave.spv"
+    }
+}
+
+mod defectcorrection {
+    vulkano_shaders::shader! {
+        bytes: "shaders/defectcorrection.spv"
+    }
+}
+
+mod awbstatistics {
+    vulkano_shaders::shader! {
+        bytes: "shaders/awbstatistics.spv"
+    }
+}
+
+mod awbreduce {
+    vulkano_shaders::shader! {
+        bytes: "shaders/awbreduce.spv"
+    }
+}
+
+mod awbgains {
+    vulkano_shaders::shader! {
+        bytes: "shaders/awbgains.spv"
+    }
+}
+
+mod normalize {
+    vulkano_shaders::shader! {
+        bytes: "shaders/normalize.spv"
+    }
+}
+
+mod highlights {
+    vulkano_shaders::shader! {
+        bytes: "shaders/highlights.spv"
+    }
+}
+
+mod denoise {
+    vulkano_shaders::shader! {
+        bytes: "shaders/denoise.spv"
+    }
+}
+
+mod lensshading {
+    vulkano_shaders::shader! {
+        bytes: "shaders/lensshading.spv"
+    }
+}
+
+mod demosaicbilinear {
+    vulkano_shaders::shader! {
+        bytes: "shaders/demosaicbilinear.spv"
+    }
+}
+
+mod demosaicmhc {
+    vulkano_shaders::shader! {
+        bytes: "shaders/demosaicmhc.spv"
+    }
+}
+
+mod demosaicmenon {
+    vulkano_shaders::shader! {
+        bytes: "shaders/demosaicmenon.spv"
+    }
+}
+
+mod colorcorrection {
+    vulkano_shaders::shader! {
+        bytes: "shaders/colorcorrection.spv"
+    }
+}
+
+mod fusionexposures {
+    vulkano_shaders::shader! {
+        bytes: "shaders/fusionexposures.spv"
+    }
+}
+
+mod fusiondownsample {
+    vulkano_shaders::shader! {
+        bytes: "shaders/fusiondownsample.spv"
+    }
+}
+
+mod fusioncollapse {
+    vulkano_shaders::shader! {
+        bytes: "shaders/fusioncollapse.spv"
+    }
+}
+
+mod fusionapply {
+    vulkano_shaders::shader! {
+        bytes: "shaders/fusionapply.spv"
+    }
+}
+
+mod tonemap {
+    vulkano_shaders::shader! {
+        bytes: "shaders/tonemap.spv"
+    }
+}
+
+mod gammacorrection {
+    vulkano_shaders::shader! {
+        bytes: "shaders/gammacorrection.spv"
+    }
+}
+
+mod quantize {
+    vulkano_shaders::shader! {
+        bytes: "shaders/quantize.spv"
+    }
+}
+
+// Variants of the shaders above reading and writing the RGB intermediates as 32-bit floats
+mod demosaicbilinear_f32 {
+    vulkano_shaders::shader! {
+        bytes: "shaders/demosaicbilinear_f32.spv"
+    }
+}
+
+mod demosaicmhc_f32 {
+    vulkano_shaders::shader! {
+        bytes: "shaders/demosaicmhc_f32.spv"
+    }
+}
+
+mod demosaicmenon_f32 {
+    vulkano_shaders::shader! {
+        bytes: "shaders/demosaicmenon_f32.spv"
+    }
+}
+
+mod colorcorrection_f32 {
+    vulkano_shaders::shader! {
+        bytes: "shaders/colorcorrection_f32.spv"
+    }
+}
+
+mod fusionexposures_f32 {
+    vulkano_shaders::shader! {
+        bytes: "shaders/fusionexposures_f32.spv"
+    }
+}
+
+mod fusionapply_f32 {
+    vulkano_shaders::shader! {
+        bytes: "shaders/fusionapply_f32.spv"
+    }
+}
+
+mod tonemap_f32 {
+    vulkano_shaders::shader! {
+        bytes: "shaders/tonemap_f32.spv"
+    }
+}
+
+mod gammacorrection_f32 {
+    vulkano_shaders::shader! {
+        bytes: "shaders/gammacorrection_f32.spv"
+    }
+}
+
+mod quantize_f32 {
+    vulkano_shaders::shader! {
+        bytes: "shaders/quantize_f32.spv"
+    }
+}
+
+// Compute shaders compiled from the shaders/ directory
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Shader {
+    Unpack,
+    ShiftBayer,
+    Grayscale,
+    Downsample,
+    Align,
+    Merge,
+    Interleave,
+    DefectCorrection,
+    AwbStatistics,
+    AwbReduce,
+    AwbGains,
+    Normalize,
+    Highlights,
+    Denoise,
+    LensShading,
+    DemosaicBilinear,
+    DemosaicMhc,
+    DemosaicMenon,
+    ColorCorrection,
+    FusionExposures,
+    FusionDownsample,
+    FusionCollapse,
+    FusionApply,
+    ToneMap,
+    GammaCorrection,
+    Quantize,
+    DemosaicBilinearF32,
+    DemosaicMhcF32,
+    DemosaicMenonF32,
+    ColorCorrectionF32,
+    FusionExposuresF32,
+    FusionApplyF32,
+    ToneMapF32,
+    GammaCorrectionF32,
+    QuantizeF32,
+}
+
+impl Shader {
+    pub const ALL: [Shader; 35] = [
+        Shader::Unpack,
+        Shader::ShiftBayer,
+        Shader::Grayscale,
+        Shader::Downsample,
+        Shader::Align,
+        Shader::Merge,
+        Shader::Interleave,
+        Shader::DefectCorrection,
+        Shader::AwbStatistics,
+        Shader::AwbReduce,
+        Shader::AwbGains,
+        Shader::Normalize,
+        Shader::Highlights,
+        Shader::Denoise,
+        Shader::LensShading,
+        Shader::DemosaicBilinear,
+        Shader::DemosaicMhc,
+        Shader::DemosaicMenon,
+        Shader::ColorCorrection,
+        Shader::FusionExposures,
+        Shader::FusionDownsample,
+        Shader::FusionCollapse,
+        Shader::FusionApply,
+        Shader::ToneMap,
+        Shader::GammaCorrection,
+        Shader::Quantize,
+        Shader::DemosaicBilinearF32,
+        Shader::DemosaicMhcF32,
+        Shader::DemosaicMenonF32,
+        Shader::ColorCorrectionF32,
+        Shader::FusionExposuresF32,
+        Shader::FusionApplyF32,
+        Shader::ToneMapF32,
+        Shader::GammaCorrectionF32,
+        Shader::QuantizeF32,
+    ];
+
+    // Variant of a shader of the RGB intermediates matching their format, the others are
+    // returned as they are
+    pub fn for_format(self, format: Format) -> Shader {
+        if format != Format::R32G32B32A32_SFLOAT {
+            return self;
+        }
+
+        match self {
+            Shader::DemosaicBilinear => Shader::DemosaicBilinearF32,
+            Shader::DemosaicMhc => Shader::DemosaicMhcF32,
+            Shader::DemosaicMenon => Shader::DemosaicMenonF32,
+            Shader::ColorCorrection => Shader::ColorCorrectionF32,
+            Shader::FusionExposures => Shader::FusionExposuresF32,
+            Shader::FusionApply => Shader::FusionApplyF32,
+            Shader::ToneMap => Shader::ToneMapF32,
+            Shader::GammaCorrection => Shader::GammaCorrectionF32,
+            Shader::Quantize => Shader::QuantizeF32,
+            _ => self,
+        }
+    }
+
+    pub fn load(self, device: Arc<Device>) -> Result<Arc<ShaderModule>, Validated<VulkanError>> {
+        match self {
+            Shader::Unpack => unpack::load(device),
+            Shader::ShiftBayer => shiftbayer::load(device),
+            Shader::Grayscale => grayscale::load(device),
+            Shader::Downsample => downsample::load(device),
+            Shader::Align => align::load(device),
+            Shader::Merge => merge::load(device),
+            Shader::Interleave => interleave::load(device),
+            Shader::DefectCorrection => defectcorrection::load(device),
+            Shader::AwbStatistics => awbstatistics::load(device),
+            Shader::AwbReduce => awbreduce::load(device),
+            Shader::AwbGains => awbgains::load(device),
+            Shader::Normalize => normalize::load(device),
+            Shader::Highlights => highlights::load(device),
+            Shader::Denoise => denoise::load(device),
+            Shader::LensShading => lensshading::load(device),
+            Shader::DemosaicBilinear => demosaicbilinear::load(device),
+            Shader::DemosaicMhc => demosaicmhc::load(device),
+            Shader::DemosaicMenon => demosaicmenon::load(device),
+            Shader::ColorCorrection => colorcorrection::load(device),
+            Shader::FusionExposures => fusionexposures::load(device),
+            Shader::FusionDownsample => fusiondownsample::load(device),
+            Shader::FusionCollapse => fusioncollapse::load(device),
+            Shader::FusionApply => fusionapply::load(device),
+            Shader::ToneMap => tonemap::load(device),
+            Shader::GammaCorrection => gammacorrection::load(device),
+            Shader::Quantize => quantize::load(device),
+            Shader::DemosaicBilinearF32 => demosaicbilinear_f32::load(device),
+            Shader::DemosaicMhcF32 => demosaicmhc_f32::load(device),
+            Shader::DemosaicMenonF32 => demosaicmenon_f32::load(device),
+            Shader::ColorCorrectionF32 => colorcorrection_f32::load(device),
+            Shader::FusionExposuresF32 => fusionexposures_f32::load(device),
+            Shader::FusionApplyF32 => fusionapply_f32::load(device),
+            Shader::ToneMapF32 => tonemap_f32::load(device),
+            Shader::GammaCorrectionF32 => gammacorrection_f32::load(device),
+            Shader::QuantizeF32 => quantize_f32::load(device),
+        }
+    }
+}
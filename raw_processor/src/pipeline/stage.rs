@@ -8,7 +8,7 @@ use vulkano::{
     pipeline::ComputePipeline,
 };
 
-use crate::pipeline::context;
+use crate::{error::RawProcessorError, pipeline::context};
 
 pub struct StageResources {
     pub compute_pipeline: Arc<ComputePipeline>,
@@ -16,14 +16,12 @@ pub struct StageResources {
 
     pub image_views: Vec<Arc<ImageView>>,
     pub buffers: Vec<Subbuffer<[u8]>>,
-    pub commands: Vec<Arc<PrimaryAutoCommandBuffer>>,
 }
 
 #[derive(Default)]
 pub struct StageOutput {
     pub image_views: Vec<Arc<ImageView>>,
     pub buffers: Vec<Subbuffer<[u8]>>,
-    pub commands: Vec<Arc<PrimaryAutoCommandBuffer>>,
 }
 
 pub trait StageInPipeline {
@@ -32,12 +30,36 @@ pub trait StageInPipeline {
         &self,
         context: &context::Context,
         input: Option<StageOutput>,
-    ) -> StageResources;
+    ) -> Result<StageResources, RawProcessorError>;
 
+    // Also records the transfers the stage owns, so that the whole pipeline is a single
+    // submission
     fn bind_stage_pipeline_and_dispatch(
         &self,
         command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
         resources: &StageResources,
         work_groups: [u32; 3],
-    );
+    ) -> Result<(), RawProcessorError>;
+}
+
+// Work groups of 8x8 invocations covering the extent
+pub fn work_groups(extent: [u32; 3]) -> [u32; 3] {
+    [extent[0].div_ceil(8), extent[1].div_ceil(8), 1]
+}
+
+// Creates the resources of a stage and records it, its output is the input of the next stage
+pub fn record_stage(
+    stage: &dyn StageInPipeline,
+    context: &context::Context,
+    command_buffer_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    input: Option<StageOutput>,
+    work_groups: [u32; 3],
+) -> Result<StageOutput, RawProcessorError> {
+    let resources = stage.create_stage_resources(context, input)?;
+    stage.bind_stage_pipeline_and_dispatch(command_buffer_builder, &resources, work_groups)?;
+
+    Ok(StageOutput {
+        image_views: resources.image_views,
+        buffers: resources.buffers,
+    })
 }
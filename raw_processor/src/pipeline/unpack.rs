@@ -0,0 +1,100 @@
+use crate::processor::RawFormat;
+
+// Unpacks MIPI CSI-2 packed rows into 16-bit little endian samples on the host, the output
+// must hold width * height samples. Row padding is skipped, the last row may omit it. Only
+// packed formats are accepted, RAW16 rows are copied to the GPU as they are.
+pub fn unpack(format: RawFormat, packed: &[u8], width: u32, row_stride: usize, output: &mut [u8]) {
+    debug_assert!(format.is_packed(), "{format:?} samples are not packed");
+
+    let output_rows = output.chunks_exact_mut(width as usize * 2);
+
+    for (packed_row, output_row) in packed.chunks(row_stride).zip(output_rows) {
+        if format == RawFormat::Raw10 {
+            for (group, samples) in packed_row
+                .chunks_exact(5)
+                .zip(output_row.chunks_exact_mut(8))
+            {
+                for index in 0..4 {
+                    let value =
+                        ((group[index] as u16) << 2) | ((group[4] as u16 >> (index * 2)) & 0x3);
+                    samples[index * 2..][..2].copy_from_slice(&value.to_le_bytes());
+                }
+            }
+        } else {
+            // RAW12
+            for (group, samples) in packed_row
+                .chunks_exact(3)
+                .zip(output_row.chunks_exact_mut(4))
+            {
+                for index in 0..2 {
+                    let value =
+                        ((group[index] as u16) << 4) | ((group[2] as u16 >> (index * 4)) & 0xf);
+                    samples[index * 2..][..2].copy_from_slice(&value.to_le_bytes());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(output: &[u8]) -> Vec<u16> {
+        output
+            .chunks_exact(2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect()
+    }
+
+    #[test]
+    fn unpacks_raw10() {
+        // Two rows of 4 samples, the first padded to 7 bytes and the last one not padded
+        let packed = [
+            0xff,
+            0x00,
+            0x55,
+            0xaa,
+            0b10_01_00_11,
+            0xee,
+            0xee, //
+            0x01,
+            0x02,
+            0x03,
+            0x04,
+            0b00_01_10_11,
+        ];
+        let mut output = [0u8; 4 * 2 * 2];
+
+        unpack(RawFormat::Raw10, &packed, 4, 7, &mut output);
+
+        assert_eq!(
+            samples(&output),
+            [0x3ff, 0x000, 0x155, 0x2aa, 0x007, 0x00a, 0x00d, 0x010]
+        );
+    }
+
+    #[test]
+    fn unpacks_raw12() {
+        // Two rows of 4 samples, the first padded to 8 bytes and the last one not padded
+        let packed = [
+            0xff, 0x00, 0x0f, 0xab, 0xcd, 0x21, 0xee, 0xee, //
+            0x80, 0x7f, 0xf0, 0x12, 0x34, 0x5a,
+        ];
+        let mut output = [0u8; 4 * 2 * 2];
+
+        unpack(RawFormat::Raw12, &packed, 4, 8, &mut output);
+
+        assert_eq!(
+            samples(&output),
+            [0xfff, 0x000, 0xab1, 0xcd2, 0x800, 0x7ff, 0x12a, 0x345]
+        );
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "not packed")]
+    fn rejects_raw16() {
+        unpack(RawFormat::Raw16, &[0; 8], 2, 4, &mut [0; 8]);
+    }
+}
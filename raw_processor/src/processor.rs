@@ -0,0 +1,468 @@
+use std::{slice, sync::Arc};
+
+use vulkano::{
+    VulkanLibrary,
+    buffer::{BufferReadGuard, Subbuffer},
+};
+
+use crate::{color::ColorSpec, error::RawProcessorError, pipeline, validation};
+
+// Sample layout of the RAW buffer, named after the android.graphics.ImageFormat constants
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RawFormat {
+    // One 16-bit little endian sample per pixel
+    #[default]
+    Raw16,
+    // MIPI CSI-2 packing, 4 pixels in 5 bytes
+    Raw10,
+    // MIPI CSI-2 packing, 2 pixels in 3 bytes
+    Raw12,
+}
+
+impl RawFormat {
+    pub fn from_image_format(image_format: i32) -> Option<RawFormat> {
+        match image_format {
+            0x20 /* RAW_SENSOR */ => Some(RawFormat::Raw16),
+            0x25 /* RAW10 */ => Some(RawFormat::Raw10),
+            0x26 /* RAW12 */ => Some(RawFormat::Raw12),
+            _ => None,
+        }
+    }
+
+    pub fn bits_per_sample(self) -> u32 {
+        match self {
+            RawFormat::Raw16 => 16,
+            RawFormat::Raw10 => 10,
+            RawFormat::Raw12 => 12,
+        }
+    }
+
+    pub fn is_packed(self) -> bool {
+        self != RawFormat::Raw16
+    }
+
+    // Length in bytes of a row of tightly packed samples
+    pub fn row_len(self, width: u32) -> usize {
+        width as usize * self.bits_per_sample() as usize / 8
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DemosaicAlgorithm {
+    // Fast and soft, meant for previews
+    Bilinear,
+    // Malvar-He-Cutler gradient corrected linear interpolation
+    #[default]
+    MalvarHeCutler,
+    // Edge directed interpolation after Menon et al., fewer zipper and false color artifacts
+    // on text and fabrics
+    Menon,
+}
+
+impl DemosaicAlgorithm {
+    // Ordinal of the DemosaicAlgorithm Kotlin enum
+    pub fn from_ordinal(ordinal: i32) -> Option<DemosaicAlgorithm> {
+        match ordinal {
+            0 => Some(DemosaicAlgorithm::Bilinear),
+            1 => Some(DemosaicAlgorithm::MalvarHeCutler),
+            2 => Some(DemosaicAlgorithm::Menon),
+            _ => None,
+        }
+    }
+}
+
+// Source of the white balance applied to the RAW samples
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum WhiteBalance {
+    // COLOR_CORRECTION_GAINS and SENSOR_NEUTRAL_COLOR_POINT chosen by the camera AWB
+    #[default]
+    Camera,
+    // Estimated from the RAW statistics, assuming the average of the scene is neutral
+    GrayWorld,
+    // Estimated from the RAW statistics, assuming the brightest unclipped values are neutral
+    WhitePatch,
+    // Estimated from the RAW statistics, assuming the average of the edges is neutral
+    GrayEdge,
+    // Scene illuminant given as a correlated color temperature in Kelvin and a tint, positive
+    // towards magenta
+    Temperature {
+        temperature: f32,
+        tint: f32,
+    },
+    // Gains of the [R, G, G, B] channels, as COLOR_CORRECTION_GAINS
+    Gains([f32; 4]),
+}
+
+impl WhiteBalance {
+    // Ordinal of the WhiteBalance Kotlin class, along with the parameters of the explicit
+    // white balances
+    pub fn from_ordinal(
+        ordinal: i32,
+        temperature: f32,
+        tint: f32,
+        gains: [f32; 4],
+    ) -> Option<WhiteBalance> {
+        match ordinal {
+            0 => Some(WhiteBalance::Camera),
+            1 => Some(WhiteBalance::GrayWorld),
+            2 => Some(WhiteBalance::WhitePatch),
+            3 => Some(WhiteBalance::GrayEdge),
+            4 => Some(WhiteBalance::Temperature { temperature, tint }),
+            5 => Some(WhiteBalance::Gains(gains)),
+            _ => None,
+        }
+    }
+}
+
+// Handling of samples clipped by the sensor
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HighlightMode {
+    // Clips every channel at the lowest clipping point, clipped highlights turn white
+    Clip,
+    // Desaturates towards white approaching the clipping point, keeping the brightness
+    Blend,
+    // Estimates clipped channels from the unclipped ones and the color of nearby pixels
+    #[default]
+    Inpaint,
+}
+
+impl HighlightMode {
+    // Ordinal of the HighlightMode Kotlin enum
+    pub fn from_ordinal(ordinal: i32) -> Option<HighlightMode> {
+        match ordinal {
+            0 => Some(HighlightMode::Clip),
+            1 => Some(HighlightMode::Blend),
+            2 => Some(HighlightMode::Inpaint),
+            _ => None,
+        }
+    }
+}
+
+// Operator mapping scene referred linear RGB into the display range
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum ToneMapOperator {
+    // Clips at 1.0, highlights are lost
+    Clip,
+    // Filmic curve fitted to the ACES rendering, with a soft shoulder and toe
+    #[default]
+    Aces,
+    // Reinhard extended, applied to the luminance which reaches 1.0 at the white point
+    Reinhard {
+        white_point: f32,
+    },
+    // Samples evenly spaced over [0, 1] interpolated linearly, applied to each channel
+    Curve(Vec<f32>),
+}
+
+impl ToneMapOperator {
+    // Ordinal of the ToneMapOperator Kotlin enum, along with the parameters of the operators
+    pub fn from_ordinal(
+        ordinal: i32,
+        white_point: f32,
+        curve: Vec<f32>,
+    ) -> Option<ToneMapOperator> {
+        match ordinal {
+            0 => Some(ToneMapOperator::Clip),
+            1 => Some(ToneMapOperator::Aces),
+            2 => Some(ToneMapOperator::Reinhard { white_point }),
+            3 => Some(ToneMapOperator::Curve(curve)),
+            _ => None,
+        }
+    }
+}
+
+// Primaries and transfer function of the output
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorSpace {
+    #[default]
+    Srgb,
+    // DCI-P3 primaries with the D65 white point and the sRGB transfer function
+    DisplayP3,
+    // ITU-R BT.2020 with the BT.709 transfer function
+    Rec2020,
+    // ITU-R BT.2100 with the perceptual quantizer, reference white at 203 cd/m2
+    Rec2020Pq,
+    // ITU-R BT.2100 with the hybrid log-gamma transfer function, reference white at 75%
+    Rec2020Hlg,
+    // ROMM RGB with its 1.8 gamma
+    ProPhoto,
+    // Scene referred linear BT.2020, neither tone mapped nor encoded
+    Linear,
+}
+
+impl ColorSpace {
+    // Ordinal of the ColorSpace Kotlin enum
+    pub fn from_ordinal(ordinal: i32) -> Option<ColorSpace> {
+        match ordinal {
+            0 => Some(ColorSpace::Srgb),
+            1 => Some(ColorSpace::DisplayP3),
+            2 => Some(ColorSpace::Rec2020),
+            3 => Some(ColorSpace::Rec2020Pq),
+            4 => Some(ColorSpace::Rec2020Hlg),
+            5 => Some(ColorSpace::ProPhoto),
+            6 => Some(ColorSpace::Linear),
+            _ => None,
+        }
+    }
+}
+
+// Pixel layout of the processed image, components in native byte order
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Rgba8,
+    // Alpha is dropped, 6 bytes per pixel
+    Rgb16,
+    Rgba16Float,
+    Rgba32Float,
+}
+
+impl OutputFormat {
+    // Ordinal of the OutputFormat Kotlin enum
+    pub fn from_ordinal(ordinal: i32) -> Option<OutputFormat> {
+        match ordinal {
+            0 => Some(OutputFormat::Rgba8),
+            1 => Some(OutputFormat::Rgb16),
+            2 => Some(OutputFormat::Rgba16Float),
+            3 => Some(OutputFormat::Rgba32Float),
+            _ => None,
+        }
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            OutputFormat::Rgba8 => 4,
+            OutputFormat::Rgb16 => 6,
+            OutputFormat::Rgba16Float => 8,
+            OutputFormat::Rgba32Float => 16,
+        }
+    }
+}
+
+// Bayer RAW frame as delivered by the camera
+pub struct RawImage<'a> {
+    pub data: &'a [u8],
+    pub format: RawFormat,
+
+    pub width: u32,
+    pub height: u32,
+
+    // Image.Plane.getRowStride and getPixelStride, in bytes. Rows may be padded past the end
+    // of the samples, the pixel stride is 2 for RAW16 and 0 for the packed formats.
+    pub row_stride: u32,
+    pub pixel_stride: u32,
+
+    // CameraCharacteristics.SENSOR_INFO_COLOR_FILTER_ARRANGEMENT
+    pub color_filter_arrangement: i32,
+
+    pub white_level: i32,
+    pub black_level: [i32; 4],
+}
+
+// CaptureResult.STATISTICS_LENS_SHADING_CORRECTION_MAP, a grid of control points spanning
+// the image with [R, Geven, Godd, B] gain factors each, in row major order
+#[derive(Clone, Debug, PartialEq)]
+pub struct LensShadingMap {
+    pub columns: u32,
+    pub rows: u32,
+    pub gains: Vec<f32>,
+}
+
+// Per-capture color metadata, named after the Camera2 keys it comes from
+#[derive(Clone, Debug)]
+pub struct ProcessingParams {
+    // CaptureResult.SENSOR_NEUTRAL_COLOR_POINT
+    pub neutral_point: [f32; 3],
+    // CaptureResult.COLOR_CORRECTION_GAINS
+    pub color_gains: [f32; 4],
+    // Replaces the neutral point and the color gains above unless it is Camera
+    pub white_balance: WhiteBalance,
+
+    // CameraCharacteristics.SENSOR_REFERENCE_ILLUMINANT1 and SENSOR_REFERENCE_ILLUMINANT2,
+    // zero when the device is calibrated for a single illuminant
+    pub reference_illuminant_1: i32,
+    pub reference_illuminant_2: i32,
+
+    // CameraCharacteristics.SENSOR_COLOR_TRANSFORM1 and SENSOR_COLOR_TRANSFORM2 (row major)
+    pub color_transform_1: [f32; 9],
+    pub color_transform_2: [f32; 9],
+
+    // CameraCharacteristics.SENSOR_FORWARD_MATRIX1 and SENSOR_FORWARD_MATRIX2 (row major)
+    pub forward_matrix_1: [f32; 9],
+    pub forward_matrix_2: [f32; 9],
+
+    pub demosaic_algorithm: DemosaicAlgorithm,
+
+    pub highlight_mode: HighlightMode,
+
+    // Only present when STATISTICS_LENS_SHADING_MAP_MODE was enabled for the capture
+    pub lens_shading_map: Option<LensShadingMap>,
+
+    // Samples further than this fraction of the white level outside the range of their
+    // same color neighbors are replaced, zero disables detection
+    pub defect_threshold: f32,
+    // Static defect list in sensor coordinates, as in the DNG FixBadPixelsList opcode or
+    // CaptureResult.STATISTICS_HOT_PIXEL_MAP
+    pub bad_pixels: Vec<[u32; 2]>,
+
+    // CaptureResult.SENSOR_NOISE_PROFILE, the (S, O) pair of each CFA channel in sensor order
+    // such that the noise variance of a normalized sample x is S * x + O
+    pub noise_profile: Option<[[f32; 2]; 4]>,
+    // Range sigma of the denoising filter in units of noise standard deviation, zero disables
+    // denoising
+    pub denoise_strength: f32,
+
+    // Spread in EV of the synthetic exposures fused by the local tone mapping, zero disables it
+    pub local_tone_map_strength: f32,
+    // Number of synthetic exposures, from 2 to 4
+    pub synthetic_exposures: u32,
+
+    pub tone_map_operator: ToneMapOperator,
+    // Exposure compensation in EV applied ahead of the tone mapping operator
+    pub exposure_bias: f32,
+
+    // Primaries and transfer function of the processed image
+    pub color_space: ColorSpace,
+    pub output_format: OutputFormat,
+}
+
+impl ProcessingParams {
+    // Color calibration of the camera, also converts between the gains and the temperature of
+    // the white balance
+    pub fn color_spec(&self) -> ColorSpec {
+        ColorSpec::from_characteristics(
+            self.reference_illuminant_1,
+            self.reference_illuminant_2,
+            self.color_transform_1,
+            self.color_transform_2,
+            self.forward_matrix_1,
+            self.forward_matrix_2,
+        )
+    }
+}
+
+// Output of the pipeline in the requested format, still held in host visible GPU memory. Rows
+// are tightly packed.
+pub struct ProcessedImage {
+    pub width: u32,
+    pub height: u32,
+    pub format: OutputFormat,
+
+    buffer: Subbuffer<[u8]>,
+}
+
+impl ProcessedImage {
+    pub fn read(&self) -> Result<BufferReadGuard<'_, [u8]>, RawProcessorError> {
+        Ok(self.buffer.read()?)
+    }
+
+    pub fn to_vec(&self) -> Result<Vec<u8>, RawProcessorError> {
+        Ok(self.read()?.to_vec())
+    }
+}
+
+// Pipeline run that has been submitted to the GPU and may still be executing
+pub struct ProcessingJob {
+    width: u32,
+    height: u32,
+    format: OutputFormat,
+
+    submission: pipeline::Submission,
+    buffer: Subbuffer<[u8]>,
+}
+
+impl ProcessingJob {
+    // Polls the fence without blocking
+    pub fn is_complete(&self) -> Result<bool, RawProcessorError> {
+        Ok(self.submission.is_signaled()?)
+    }
+
+    // Blocks until the GPU is done and returns the output
+    pub fn wait(self) -> Result<ProcessedImage, RawProcessorError> {
+        self.submission.wait(None)?;
+
+        Ok(ProcessedImage {
+            width: self.width,
+            height: self.height,
+            format: self.format,
+            buffer: self.buffer,
+        })
+    }
+}
+
+pub struct Processor {
+    context: Box<pipeline::Context>,
+}
+
+impl Processor {
+    pub fn new(
+        library: Arc<VulkanLibrary>,
+        create_info: pipeline::ContextCreateInfo,
+    ) -> Result<Processor, RawProcessorError> {
+        Ok(Processor {
+            context: pipeline::Context::new(library, create_info)?,
+        })
+    }
+
+    pub fn capabilities(&self) -> &pipeline::DeviceCapabilities {
+        &self.context.capabilities
+    }
+
+    // Frees pooled images and staging buffers that are not held by any ProcessedImage
+    pub fn trim(&self) {
+        self.context.pool.trim();
+    }
+
+    // Writes compiled pipelines back to ContextCreateInfo::pipeline_cache_path
+    pub fn save_pipeline_cache(&self) -> Result<(), RawProcessorError> {
+        self.context.save_pipeline_cache()
+    }
+
+    // Returns as soon as the frame has been uploaded and submitted, the RAW data may be
+    // released right away while the job is still executing.
+    pub fn submit(
+        &self,
+        image: &RawImage,
+        params: &ProcessingParams,
+    ) -> Result<ProcessingJob, RawProcessorError> {
+        self.submit_burst(slice::from_ref(image), params)
+    }
+
+    // Aligns and merges a burst of frames of the same scene into the first one before
+    // processing it, params describe the first frame
+    pub fn submit_burst(
+        &self,
+        frames: &[RawImage],
+        params: &ProcessingParams,
+    ) -> Result<ProcessingJob, RawProcessorError> {
+        // Nothing is recorded for frames with malformed metadata
+        validation::validate_burst(frames, &self.context.capabilities)?;
+        validation::validate_params(&frames[0], params)?;
+
+        let (submission, buffer) = pipeline::Finish::new().submit(&self.context, frames, params)?;
+
+        Ok(ProcessingJob {
+            width: frames[0].width,
+            height: frames[0].height,
+            format: params.output_format,
+            submission,
+            buffer,
+        })
+    }
+
+    pub fn process(
+        &self,
+        image: &RawImage,
+        params: &ProcessingParams,
+    ) -> Result<ProcessedImage, RawProcessorError> {
+        self.submit(image, params)?.wait()
+    }
+
+    pub fn process_burst(
+        &self,
+        frames: &[RawImage],
+        params: &ProcessingParams,
+    ) -> Result<ProcessedImage, RawProcessorError> {
+        self.submit_burst(frames, params)?.wait()
+    }
+}
@@ -0,0 +1,753 @@
+use std::fmt;
+
+use crate::{
+    color,
+    pipeline::DeviceCapabilities,
+    processor::{ProcessingParams, RawFormat, RawImage, ToneMapOperator, WhiteBalance},
+};
+
+// Malformed input or capture metadata, rejected before any GPU work is recorded
+#[derive(Clone, Debug, PartialEq)]
+pub enum InvalidInput {
+    // JNI argument that must not be null
+    NullArgument(&'static str),
+    // JNI array holding fewer elements than expected
+    ArrayLength {
+        name: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+    // JNI integer that is cast to an unsigned size or coordinate
+    NegativeArgument {
+        name: &'static str,
+        value: i32,
+    },
+
+    // android.graphics.ImageFormat that is not a RAW format
+    Format(i32),
+    // Empty frame, or one that does not hold whole 2x2 Bayer quads or whole packed groups
+    Dimensions {
+        width: u32,
+        height: u32,
+    },
+    // Larger than the images the device can create
+    TooLarge {
+        width: u32,
+        height: u32,
+        max: u32,
+    },
+    RowStride {
+        row_stride: u32,
+        min: usize,
+    },
+    // RAW16 rows are copied as rows of 16-bit texels, they have to start on a whole sample
+    UnalignedRowStride(u32),
+    // RAW16 samples are not laid out contiguously within a row
+    PixelStride(u32),
+    BufferLength {
+        expected: usize,
+        actual: usize,
+    },
+
+    // Only the RGGB, GRBG, GBRG and BGGR arrangements are supported
+    ColorFilterArrangement(i32),
+    Levels {
+        white_level: i32,
+        black_level: [i32; 4],
+    },
+    ColorGains([f32; 4]),
+    NeutralPoint([f32; 3]),
+    WhiteBalance(i32),
+    // Outside of the range covered by the isotemperature lines, or not finite
+    Temperature {
+        temperature: f32,
+        tint: f32,
+    },
+    // Non-finite or singular calibration matrix
+    Matrix(&'static str),
+    DemosaicAlgorithm(i32),
+    HighlightMode(i32),
+    // Empty grid, gain count not matching the grid, or gains that are not positive
+    LensShadingMap {
+        columns: u32,
+        rows: u32,
+        len: usize,
+    },
+    DefectThreshold(f32),
+    // Static defect outside of the frame
+    BadPixel([u32; 2]),
+    // Bad pixel coordinates that do not come in x, y pairs
+    BadPixels(usize),
+    NoiseProfile([[f32; 2]; 4]),
+    DenoiseStrength(f32),
+    EmptyBurst,
+    LocalToneMapStrength(f32),
+    SyntheticExposures(u32),
+    ToneMapOperator(i32),
+    WhitePoint(f32),
+    // Fewer than two samples, or samples that are not finite
+    ToneCurve(usize),
+    ExposureBias(f32),
+    ColorSpace(i32),
+    OutputFormat(i32),
+    // Frame of a burst whose dimensions or color filter arrangement differ from the first one
+    BurstFrame(usize),
+}
+
+impl fmt::Display for InvalidInput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidInput::NullArgument(name) => write!(f, "{name} is null"),
+            InvalidInput::ArrayLength {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{name} holds {actual} elements, expected at least {expected}"
+            ),
+            InvalidInput::NegativeArgument { name, value } => {
+                write!(f, "{name} is negative ({value})")
+            }
+            InvalidInput::Format(format) => write!(f, "unsupported RAW image format {format:#x}"),
+            InvalidInput::Dimensions { width, height } => {
+                write!(f, "invalid image dimensions {width}x{height}")
+            }
+            InvalidInput::TooLarge { width, height, max } => write!(
+                f,
+                "image of {width}x{height} exceeds the device limit of {max} pixels"
+            ),
+            InvalidInput::RowStride { row_stride, min } => write!(
+                f,
+                "row stride of {row_stride} bytes is shorter than a row of {min} bytes"
+            ),
+            InvalidInput::UnalignedRowStride(row_stride) => write!(
+                f,
+                "RAW16 row stride of {row_stride} bytes is not a whole number of samples"
+            ),
+            InvalidInput::PixelStride(pixel_stride) => {
+                write!(f, "unsupported RAW16 pixel stride of {pixel_stride} bytes")
+            }
+            InvalidInput::BufferLength { expected, actual } => write!(
+                f,
+                "RAW buffer holds {actual} bytes, expected at least {expected}"
+            ),
+            InvalidInput::ColorFilterArrangement(color_filter_arrangement) => write!(
+                f,
+                "unsupported color filter arrangement {color_filter_arrangement}"
+            ),
+            InvalidInput::Levels {
+                white_level,
+                black_level,
+            } => write!(
+                f,
+                "white level {white_level} is not above black levels {black_level:?}"
+            ),
+            InvalidInput::ColorGains(color_gains) => {
+                write!(f, "color gains {color_gains:?} must be positive")
+            }
+            InvalidInput::NeutralPoint(neutral_point) => {
+                write!(f, "neutral point {neutral_point:?} must be positive")
+            }
+            InvalidInput::WhiteBalance(ordinal) => {
+                write!(f, "unknown white balance {ordinal}")
+            }
+            InvalidInput::Temperature { temperature, tint } => {
+                write!(f, "invalid white balance of {temperature}K, tint {tint}")
+            }
+            InvalidInput::Matrix(name) => write!(f, "{name} is not an invertible matrix"),
+            InvalidInput::DemosaicAlgorithm(ordinal) => {
+                write!(f, "unknown demosaic algorithm {ordinal}")
+            }
+            InvalidInput::HighlightMode(ordinal) => {
+                write!(f, "unknown highlight mode {ordinal}")
+            }
+            InvalidInput::LensShadingMap { columns, rows, len } => write!(
+                f,
+                "invalid lens shading map of {columns}x{rows} points with {len} gains"
+            ),
+            InvalidInput::DefectThreshold(threshold) => {
+                write!(f, "invalid defect threshold {threshold}")
+            }
+            InvalidInput::BadPixel([x, y]) => {
+                write!(f, "bad pixel ({x}, {y}) lies outside of the frame")
+            }
+            InvalidInput::BadPixels(len) => {
+                write!(f, "{len} bad pixel coordinates do not form x, y pairs")
+            }
+            InvalidInput::NoiseProfile(noise_profile) => {
+                write!(f, "invalid noise profile {noise_profile:?}")
+            }
+            InvalidInput::DenoiseStrength(strength) => {
+                write!(f, "invalid denoise strength {strength}")
+            }
+            InvalidInput::LocalToneMapStrength(strength) => {
+                write!(f, "invalid local tone mapping strength {strength}")
+            }
+            InvalidInput::SyntheticExposures(exposures) => {
+                write!(f, "{exposures} synthetic exposures, expected 2 to 4")
+            }
+            InvalidInput::ToneMapOperator(ordinal) => {
+                write!(f, "unknown tone mapping operator {ordinal}")
+            }
+            InvalidInput::WhitePoint(white_point) => {
+                write!(f, "white point {white_point} must be positive")
+            }
+            InvalidInput::ToneCurve(len) => {
+                write!(f, "invalid tone curve of {len} samples")
+            }
+            InvalidInput::ExposureBias(exposure_bias) => {
+                write!(f, "invalid exposure bias {exposure_bias} EV")
+            }
+            InvalidInput::ColorSpace(ordinal) => {
+                write!(f, "unknown output color space {ordinal}")
+            }
+            InvalidInput::OutputFormat(ordinal) => {
+                write!(f, "unknown output format {ordinal}")
+            }
+            InvalidInput::EmptyBurst => write!(f, "burst holds no frames"),
+            InvalidInput::BurstFrame(index) => write!(
+                f,
+                "burst frame {index} does not match the dimensions and color filter arrangement of the first frame"
+            ),
+        }
+    }
+}
+
+// Range of the explicit white balance, the isotemperature lines reach down to 1667K
+const MIN_TEMPERATURE: f32 = 2000.0;
+const MAX_TEMPERATURE: f32 = 50000.0;
+const MAX_TINT: f32 = 150.0;
+
+fn is_positive(values: &[f32]) -> bool {
+    values.iter().all(|value| value.is_finite() && *value > 0.0)
+}
+
+fn is_invertible(m: [f32; 9]) -> bool {
+    m.iter().all(|value| value.is_finite())
+        && color::invert(&color::matrix_from_row_major(m)).is_some()
+}
+
+pub fn validate_image(
+    image: &RawImage,
+    capabilities: &DeviceCapabilities,
+) -> Result<(), InvalidInput> {
+    let RawImage {
+        width,
+        height,
+        format,
+        ..
+    } = *image;
+
+    let group_width = match format {
+        RawFormat::Raw16 => 2,
+        RawFormat::Raw10 => 4,
+        RawFormat::Raw12 => 2,
+    };
+
+    if width == 0 || height == 0 || width % group_width != 0 || height % 2 != 0 {
+        return Err(InvalidInput::Dimensions { width, height });
+    }
+
+    let max = capabilities.max_image_dimension_2d;
+    if width > max || height > max {
+        return Err(InvalidInput::TooLarge { width, height, max });
+    }
+
+    if !format.is_packed() && image.pixel_stride != 2 {
+        return Err(InvalidInput::PixelStride(image.pixel_stride));
+    }
+
+    if !format.is_packed() && !image.row_stride.is_multiple_of(2) {
+        return Err(InvalidInput::UnalignedRowStride(image.row_stride));
+    }
+
+    let row_len = format.row_len(width);
+    if (image.row_stride as usize) < row_len {
+        return Err(InvalidInput::RowStride {
+            row_stride: image.row_stride,
+            min: row_len,
+        });
+    }
+
+    // The padding of the last row is usually left out of the buffer, a length that does not fit
+    // in memory can never be satisfied
+    let expected = (height as usize - 1)
+        .checked_mul(image.row_stride as usize)
+        .and_then(|len| len.checked_add(row_len))
+        .unwrap_or(usize::MAX);
+    if image.data.len() < expected {
+        return Err(InvalidInput::BufferLength {
+            expected,
+            actual: image.data.len(),
+        });
+    }
+
+    if !(0..=3).contains(&image.color_filter_arrangement) {
+        return Err(InvalidInput::ColorFilterArrangement(
+            image.color_filter_arrangement,
+        ));
+    }
+
+    let max_level = (1 << format.bits_per_sample()) - 1;
+    if image.white_level > max_level
+        || image
+            .black_level
+            .iter()
+            .any(|&black_level| black_level < 0 || black_level >= image.white_level)
+    {
+        return Err(InvalidInput::Levels {
+            white_level: image.white_level,
+            black_level: image.black_level,
+        });
+    }
+
+    Ok(())
+}
+
+// Every frame must be valid on its own and line up with the first one
+pub fn validate_burst(
+    frames: &[RawImage],
+    capabilities: &DeviceCapabilities,
+) -> Result<(), InvalidInput> {
+    let reference = frames.first().ok_or(InvalidInput::EmptyBurst)?;
+
+    for (index, frame) in frames.iter().enumerate() {
+        validate_image(frame, capabilities)?;
+
+        if frame.width != reference.width
+            || frame.height != reference.height
+            || frame.color_filter_arrangement != reference.color_filter_arrangement
+        {
+            return Err(InvalidInput::BurstFrame(index));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn validate_params(image: &RawImage, params: &ProcessingParams) -> Result<(), InvalidInput> {
+    if !is_positive(&params.color_gains) {
+        return Err(InvalidInput::ColorGains(params.color_gains));
+    }
+
+    if !is_positive(&params.neutral_point) {
+        return Err(InvalidInput::NeutralPoint(params.neutral_point));
+    }
+
+    match params.white_balance {
+        WhiteBalance::Temperature { temperature, tint } => {
+            validate_temperature(temperature, tint)?;
+        }
+        WhiteBalance::Gains(gains) if !is_positive(&gains) => {
+            return Err(InvalidInput::ColorGains(gains));
+        }
+        _ => {}
+    }
+
+    validate_calibration(
+        params.color_transform_1,
+        params.color_transform_2,
+        params.forward_matrix_1,
+        params.forward_matrix_2,
+    )?;
+
+    if let Some(map) = &params.lens_shading_map
+        && (map.columns == 0
+            || map.rows == 0
+            || map.gains.len() != map.columns as usize * map.rows as usize * 4
+            || !is_positive(&map.gains))
+    {
+        return Err(InvalidInput::LensShadingMap {
+            columns: map.columns,
+            rows: map.rows,
+            len: map.gains.len(),
+        });
+    }
+
+    if !params.defect_threshold.is_finite() || params.defect_threshold < 0.0 {
+        return Err(InvalidInput::DefectThreshold(params.defect_threshold));
+    }
+
+    if let Some(&bad_pixel) = params
+        .bad_pixels
+        .iter()
+        .find(|[x, y]| *x >= image.width || *y >= image.height)
+    {
+        return Err(InvalidInput::BadPixel(bad_pixel));
+    }
+
+    if let Some(noise_profile) = params.noise_profile
+        && noise_profile
+            .iter()
+            .any(|&[s, o]| !s.is_finite() || !o.is_finite() || s < 0.0)
+    {
+        return Err(InvalidInput::NoiseProfile(noise_profile));
+    }
+
+    if !params.denoise_strength.is_finite() || params.denoise_strength < 0.0 {
+        return Err(InvalidInput::DenoiseStrength(params.denoise_strength));
+    }
+
+    if !params.local_tone_map_strength.is_finite() || params.local_tone_map_strength < 0.0 {
+        return Err(InvalidInput::LocalToneMapStrength(
+            params.local_tone_map_strength,
+        ));
+    }
+
+    // Exposures are packed into the 4 channels of the fusion pyramids
+    if !(2..=4).contains(&params.synthetic_exposures) {
+        return Err(InvalidInput::SyntheticExposures(params.synthetic_exposures));
+    }
+
+    match &params.tone_map_operator {
+        ToneMapOperator::Reinhard { white_point }
+            if !white_point.is_finite() || *white_point <= 0.0 =>
+        {
+            return Err(InvalidInput::WhitePoint(*white_point));
+        }
+        ToneMapOperator::Curve(curve)
+            if curve.len() < 2 || curve.iter().any(|value| !value.is_finite()) =>
+        {
+            return Err(InvalidInput::ToneCurve(curve.len()));
+        }
+        _ => {}
+    }
+
+    if !params.exposure_bias.is_finite() {
+        return Err(InvalidInput::ExposureBias(params.exposure_bias));
+    }
+
+    Ok(())
+}
+
+pub fn validate_temperature(temperature: f32, tint: f32) -> Result<(), InvalidInput> {
+    if !(MIN_TEMPERATURE..=MAX_TEMPERATURE).contains(&temperature)
+        || !tint.is_finite()
+        || tint.abs() > MAX_TINT
+    {
+        return Err(InvalidInput::Temperature { temperature, tint });
+    }
+
+    Ok(())
+}
+
+pub fn validate_calibration(
+    color_transform_1: [f32; 9],
+    color_transform_2: [f32; 9],
+    forward_matrix_1: [f32; 9],
+    forward_matrix_2: [f32; 9],
+) -> Result<(), InvalidInput> {
+    if !is_invertible(color_transform_1) {
+        return Err(InvalidInput::Matrix("color transform 1"));
+    }
+
+    if !is_invertible(forward_matrix_1) {
+        return Err(InvalidInput::Matrix("forward matrix 1"));
+    }
+
+    // The second calibration is left zeroed on devices calibrated for a single illuminant
+    if color_transform_2.iter().any(|&value| value != 0.0) {
+        if !is_invertible(color_transform_2) {
+            return Err(InvalidInput::Matrix("color transform 2"));
+        }
+
+        if !is_invertible(forward_matrix_2) {
+            return Err(InvalidInput::Matrix("forward matrix 2"));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use vulkano::{
+        Version,
+        device::{DeviceFeatures, physical::PhysicalDeviceType},
+    };
+
+    use super::*;
+    use crate::processor::{
+        ColorSpace, DemosaicAlgorithm, HighlightMode, LensShadingMap, OutputFormat,
+    };
+
+    const IDENTITY: [f32; 9] = [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0];
+
+    fn capabilities() -> DeviceCapabilities {
+        DeviceCapabilities {
+            name: String::new(),
+            device_type: PhysicalDeviceType::Other,
+            vendor_id: 0,
+            device_id: 0,
+            api_version: Version::V1_1,
+            driver_version: 0,
+            driver_name: None,
+            queue_family_index: 0,
+            dedicated_compute_queue: false,
+            enabled_features: DeviceFeatures::empty(),
+            max_compute_work_group_count: [65535; 3],
+            max_compute_work_group_size: [1024, 1024, 64],
+            max_compute_work_group_invocations: 1024,
+            max_push_constants_size: 128,
+            max_storage_buffer_range: u32::MAX,
+            max_image_dimension_2d: 4096,
+            subgroup_size: None,
+        }
+    }
+
+    // 8x4 RAW16 frame with rows padded to 20 bytes, the last row is not padded
+    fn image(data: &[u8]) -> RawImage<'_> {
+        RawImage {
+            data,
+            format: RawFormat::Raw16,
+            width: 8,
+            height: 4,
+            row_stride: 20,
+            pixel_stride: 2,
+            color_filter_arrangement: 0,
+            white_level: 1023,
+            black_level: [64; 4],
+        }
+    }
+
+    fn params() -> ProcessingParams {
+        ProcessingParams {
+            neutral_point: [0.5, 1.0, 0.6],
+            color_gains: [2.0, 1.0, 1.0, 1.6],
+            white_balance: WhiteBalance::Camera,
+            reference_illuminant_1: 21,
+            reference_illuminant_2: 0,
+            color_transform_1: IDENTITY,
+            color_transform_2: [0.0; 9],
+            forward_matrix_1: IDENTITY,
+            forward_matrix_2: [0.0; 9],
+            demosaic_algorithm: DemosaicAlgorithm::default(),
+            highlight_mode: HighlightMode::default(),
+            lens_shading_map: None,
+            defect_threshold: 0.0,
+            bad_pixels: vec![],
+            noise_profile: None,
+            denoise_strength: 0.0,
+            local_tone_map_strength: 0.0,
+            synthetic_exposures: 3,
+            tone_map_operator: ToneMapOperator::default(),
+            exposure_bias: 0.0,
+            color_space: ColorSpace::default(),
+            output_format: OutputFormat::default(),
+        }
+    }
+
+    #[test]
+    fn accepts_image_without_last_row_padding() {
+        let data = [0u8; 3 * 20 + 16];
+
+        assert_eq!(validate_image(&image(&data), &capabilities()), Ok(()));
+    }
+
+    #[test]
+    fn rejects_malformed_images() {
+        let data = [0u8; 4 * 20];
+        let capabilities = capabilities();
+
+        let cases = [
+            (
+                RawImage {
+                    width: 7,
+                    ..image(&data)
+                },
+                InvalidInput::Dimensions {
+                    width: 7,
+                    height: 4,
+                },
+            ),
+            (
+                RawImage {
+                    format: RawFormat::Raw10,
+                    width: 6,
+                    ..image(&data)
+                },
+                InvalidInput::Dimensions {
+                    width: 6,
+                    height: 4,
+                },
+            ),
+            (
+                RawImage {
+                    width: 8192,
+                    ..image(&data)
+                },
+                InvalidInput::TooLarge {
+                    width: 8192,
+                    height: 4,
+                    max: 4096,
+                },
+            ),
+            (
+                RawImage {
+                    pixel_stride: 4,
+                    ..image(&data)
+                },
+                InvalidInput::PixelStride(4),
+            ),
+            (
+                RawImage {
+                    row_stride: 19,
+                    ..image(&data)
+                },
+                InvalidInput::UnalignedRowStride(19),
+            ),
+            (
+                RawImage {
+                    row_stride: 14,
+                    ..image(&data)
+                },
+                InvalidInput::RowStride {
+                    row_stride: 14,
+                    min: 16,
+                },
+            ),
+            (
+                image(&data[..3 * 20 + 15]),
+                InvalidInput::BufferLength {
+                    expected: 3 * 20 + 16,
+                    actual: 3 * 20 + 15,
+                },
+            ),
+            (
+                RawImage {
+                    color_filter_arrangement: 4,
+                    ..image(&data)
+                },
+                InvalidInput::ColorFilterArrangement(4),
+            ),
+            (
+                RawImage {
+                    black_level: [64, 64, 1023, 64],
+                    ..image(&data)
+                },
+                InvalidInput::Levels {
+                    white_level: 1023,
+                    black_level: [64, 64, 1023, 64],
+                },
+            ),
+        ];
+
+        for (image, expected) in cases {
+            assert_eq!(validate_image(&image, &capabilities), Err(expected));
+        }
+    }
+
+    #[test]
+    fn rejects_huge_row_stride() {
+        let data = [0u8; 4 * 20];
+        let image = RawImage {
+            height: 4096,
+            row_stride: u32::MAX - 1,
+            ..image(&data)
+        };
+
+        assert!(matches!(
+            validate_image(&image, &capabilities()),
+            Err(InvalidInput::BufferLength { actual: 80, .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_mismatched_burst() {
+        let data = [0u8; 4 * 20];
+        let capabilities = capabilities();
+
+        assert_eq!(
+            validate_burst(&[], &capabilities),
+            Err(InvalidInput::EmptyBurst)
+        );
+
+        let frames = [
+            image(&data),
+            RawImage {
+                color_filter_arrangement: 1,
+                ..image(&data)
+            },
+        ];
+        assert_eq!(
+            validate_burst(&frames, &capabilities),
+            Err(InvalidInput::BurstFrame(1))
+        );
+    }
+
+    #[test]
+    fn accepts_valid_params() {
+        let data = [0u8; 4 * 20];
+
+        assert_eq!(validate_params(&image(&data), &params()), Ok(()));
+    }
+
+    #[test]
+    fn rejects_invalid_params() {
+        let data = [0u8; 4 * 20];
+
+        let cases = [
+            (
+                ProcessingParams {
+                    white_balance: WhiteBalance::Temperature {
+                        temperature: 1500.0,
+                        tint: 0.0,
+                    },
+                    ..params()
+                },
+                InvalidInput::Temperature {
+                    temperature: 1500.0,
+                    tint: 0.0,
+                },
+            ),
+            (
+                ProcessingParams {
+                    forward_matrix_1: [0.0; 9],
+                    ..params()
+                },
+                InvalidInput::Matrix("forward matrix 1"),
+            ),
+            (
+                ProcessingParams {
+                    lens_shading_map: Some(LensShadingMap {
+                        columns: 2,
+                        rows: 2,
+                        gains: vec![1.0; 15],
+                    }),
+                    ..params()
+                },
+                InvalidInput::LensShadingMap {
+                    columns: 2,
+                    rows: 2,
+                    len: 15,
+                },
+            ),
+            (
+                ProcessingParams {
+                    bad_pixels: vec![[1, 1], [8, 0]],
+                    ..params()
+                },
+                InvalidInput::BadPixel([8, 0]),
+            ),
+            (
+                ProcessingParams {
+                    synthetic_exposures: 5,
+                    ..params()
+                },
+                InvalidInput::SyntheticExposures(5),
+            ),
+            (
+                ProcessingParams {
+                    tone_map_operator: ToneMapOperator::Curve(vec![0.0]),
+                    ..params()
+                },
+                InvalidInput::ToneCurve(1),
+            ),
+        ];
+
+        for (params, expected) in cases {
+            assert_eq!(validate_params(&image(&data), &params), Err(expected));
+        }
+    }
+}